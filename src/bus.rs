@@ -0,0 +1,395 @@
+// bus.rs
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::blocking::Connection;
+use dbus::{Message, MethodErr};
+use dbus_crossroads::Crossroads;
+use diary_app::{Entry, Storage};
+use std::error::Error;
+use std::sync::mpsc as std_mpsc;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+use crate::control::{dispatch, ControlCommand, ControlRequest};
+use crate::scheduler::Prompt;
+
+pub const BUS_NAME: &str = "com.ravinderpayal.DiaryApp";
+pub const OBJECT_PATH: &str = "/com/ravinderpayal/DiaryApp";
+/// How often the bus thread looks for signals to send between method calls.
+const EVENT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Something the daemon announces as a D-Bus signal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusEvent {
+    EntrySaved(NaiveDate),
+    ReminderDue(Prompt, NaiveDateTime),
+}
+
+impl BusEvent {
+    fn to_message(&self) -> Message {
+        let signal = |member: &'static str| {
+            Message::signal(&OBJECT_PATH.into(), &BUS_NAME.into(), &member.into())
+        };
+        match self {
+            BusEvent::EntrySaved(date) => signal("EntrySaved").append1(date.to_string()),
+            BusEvent::ReminderDue(prompt, due_at) => signal("ReminderDue").append2(
+                prompt.to_string(),
+                due_at.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            ),
+        }
+    }
+}
+
+/// The `com.ravinderpayal.DiaryApp` session bus service.
+///
+/// Method calls are answered by the daemon through the same requests as the control
+/// socket, the bus itself runs on a thread of its own.
+#[derive(Clone)]
+pub struct BusService {
+    events: std_mpsc::Sender<BusEvent>,
+}
+
+/// State behind the exported object.
+struct Diary {
+    control: mpsc::Sender<ControlRequest>,
+    runtime: Handle,
+}
+
+impl Diary {
+    fn call(&self, command: ControlCommand) -> Result<String, MethodErr> {
+        self.runtime
+            .block_on(dispatch(&self.control, command))
+            .map_err(|err| MethodErr::failed(&err))
+    }
+}
+
+impl BusService {
+    /// Claims the bus name and starts answering calls, must be called from within the
+    /// tokio runtime.
+    pub fn start(control: mpsc::Sender<ControlRequest>) -> Result<Self, Box<dyn Error>> {
+        Self::start_on(Connection::new_session()?, control)
+    }
+
+    fn start_on(
+        connection: Connection,
+        control: mpsc::Sender<ControlRequest>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let reply = connection.request_name(BUS_NAME, false, true, true)?;
+        if reply != RequestNameReply::PrimaryOwner {
+            return Err(format!("{} is already taken on the session bus", BUS_NAME).into());
+        }
+
+        let diary = Diary {
+            control,
+            runtime: Handle::current(),
+        };
+        let (events, received) = std_mpsc::channel();
+        std::thread::spawn(move || {
+            if let Err(err) = serve(connection, diary, received) {
+                eprintln!("D-Bus service stopped: {}", err);
+            }
+        });
+        Ok(BusService { events })
+    }
+
+    pub fn emit(&self, event: BusEvent) {
+        // Nothing to do about a stopped bus thread, it has reported why already
+        let _ = self.events.send(event);
+    }
+}
+
+fn serve(
+    connection: Connection,
+    diary: Diary,
+    events: std_mpsc::Receiver<BusEvent>,
+) -> Result<(), dbus::Error> {
+    let mut crossroads = Crossroads::new();
+    let interface = crossroads.register(BUS_NAME, |b| {
+        b.method("LaunchEditor", (), (), |_, diary: &mut Diary, ()| {
+            diary.call(ControlCommand::Open).map(|_| ())
+        });
+        b.method(
+            "Snooze",
+            ("minutes",),
+            (),
+            |_, diary: &mut Diary, (minutes,): (u32,)| {
+                diary
+                    .call(ControlCommand::Snooze(Duration::minutes(
+                        minutes.max(1).into(),
+                    )))
+                    .map(|_| ())
+            },
+        );
+        b.method(
+            "GetEntry",
+            ("date",),
+            ("content",),
+            |_, diary: &mut Diary, (date,): (String,)| {
+                // An empty date asks for today's entry
+                let date = match date.as_str() {
+                    "" => None,
+                    date => Some(date.parse().map_err(|_| {
+                        MethodErr::invalid_arg(&format!("`{}`, expected YYYY-MM-DD", date))
+                    })?),
+                };
+                diary
+                    .call(ControlCommand::Entry(date))
+                    .map(|content| (content,))
+            },
+        );
+        b.method(
+            "AppendNote",
+            ("note",),
+            (),
+            |_, diary: &mut Diary, (note,): (String,)| {
+                if note.trim().is_empty() {
+                    return Err(MethodErr::invalid_arg("an empty note"));
+                }
+                diary.call(ControlCommand::Append(note)).map(|_| ())
+            },
+        );
+        b.method("Status", (), ("status",), |_, diary: &mut Diary, ()| {
+            diary.call(ControlCommand::Status).map(|status| (status,))
+        });
+        b.signal::<(String,), _>("EntrySaved", ("date",));
+        b.signal::<(String, String), _>("ReminderDue", ("kind", "due"));
+    });
+    crossroads.insert(OBJECT_PATH, &[interface], diary);
+
+    use dbus::channel::{MatchingReceiver, Sender};
+    connection.start_receive(
+        dbus::message::MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = crossroads.handle_message(message, connection);
+            true
+        }),
+    );
+    loop {
+        connection.process(EVENT_INTERVAL)?;
+        for event in events.try_iter() {
+            connection
+                .send(event.to_message())
+                .map_err(|_| dbus::Error::new_failed("Failed to send a signal"))?;
+        }
+    }
+}
+
+/// Announces every saved entry with an `EntrySaved` signal.
+pub struct SignallingStorage {
+    bus: BusService,
+    backend: Box<dyn Storage>,
+}
+
+impl SignallingStorage {
+    pub fn new(bus: BusService, backend: Box<dyn Storage>) -> Self {
+        SignallingStorage { bus, backend }
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for SignallingStorage {
+    async fn save_entry(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
+        self.backend.save_entry(date, content).await?;
+        self.bus.emit(BusEvent::EntrySaved(date));
+        Ok(())
+    }
+
+    async fn get_entry(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>> {
+        self.backend.get_entry(date).await
+    }
+
+    async fn get_latest_entry(&self) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>> {
+        self.backend.get_latest_entry().await
+    }
+
+    async fn list_entries(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        self.backend.list_entries().await
+    }
+
+    async fn get_entry_with_metadata(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<Entry>, Box<dyn Error>> {
+        self.backend.get_entry_with_metadata(date).await
+    }
+
+    async fn save_entry_with_metadata(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        self.backend.save_entry_with_metadata(entry).await?;
+        self.bus.emit(BusEvent::EntrySaved(entry.date));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::blocking::Proxy;
+    use dbus::channel::Channel;
+    use dbus::message::{MatchRule, MessageType};
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+
+    const CALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    /// A private session bus, stopped when dropped.
+    struct TestBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl TestBus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon has to be installed for the D-Bus tests");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            TestBus {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn connect(&self) -> Connection {
+            let mut channel = Channel::open_private(&self.address).unwrap();
+            channel.register().unwrap();
+            Connection::from(channel)
+        }
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "runs a dbus-daemon"]
+    async fn answers_method_calls_through_the_daemon() {
+        let bus = TestBus::start();
+        // Stands in for the daemon, answering with the command it got
+        let (control, mut requests) = mpsc::channel::<ControlRequest>(8);
+        let (received, mut commands) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                let answer = format!("{:?}", request.command);
+                let _ = received.send(request.command.clone());
+                request.respond(answer);
+            }
+        });
+        BusService::start_on(bus.connect(), control).unwrap();
+
+        let client = bus.connect();
+        let answers = tokio::task::spawn_blocking(move || {
+            let proxy = Proxy::new(BUS_NAME, OBJECT_PATH, CALL_TIMEOUT, &client);
+            let (status,): (String,) = proxy.method_call(BUS_NAME, "Status", ()).unwrap();
+            let () = proxy.method_call(BUS_NAME, "LaunchEditor", ()).unwrap();
+            let () = proxy.method_call(BUS_NAME, "Snooze", (15u32,)).unwrap();
+            let (entry,): (String,) = proxy
+                .method_call(BUS_NAME, "GetEntry", ("2024-03-05",))
+                .unwrap();
+            let bad_date: Result<(String,), _> =
+                proxy.method_call(BUS_NAME, "GetEntry", ("yesterday",));
+            let () = proxy
+                .method_call(BUS_NAME, "AppendNote", ("Called the bank",))
+                .unwrap();
+            let empty_note: Result<(), _> = proxy.method_call(BUS_NAME, "AppendNote", (" \n",));
+            (status, entry, bad_date.is_err(), empty_note.is_err())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            answers,
+            (
+                "Status".to_string(),
+                "Entry(Some(2024-03-05))".to_string(),
+                true,
+                true
+            )
+        );
+        let date = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        for expected in [
+            ControlCommand::Status,
+            ControlCommand::Open,
+            ControlCommand::Snooze(Duration::minutes(15)),
+            ControlCommand::Entry(Some(date)),
+            ControlCommand::Append("Called the bank".to_string()),
+        ] {
+            assert_eq!(commands.recv().await, Some(expected));
+        }
+        // The bad date and the empty note never got to the daemon
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "runs a dbus-daemon"]
+    async fn sends_signals_for_events() {
+        let bus = TestBus::start();
+        let (control, _requests) = mpsc::channel::<ControlRequest>(8);
+        let service = BusService::start_on(bus.connect(), control).unwrap();
+
+        let client = bus.connect();
+        let (signals, received) = std_mpsc::channel();
+        // Every signal of the interface
+        let rule = MatchRule::new()
+            .with_type(MessageType::Signal)
+            .with_interface(BUS_NAME);
+        client.add_match_no_cb(&rule.match_str()).unwrap();
+        use dbus::channel::MatchingReceiver;
+        client.start_receive(
+            rule,
+            Box::new(move |message, _| {
+                let _ = signals.send(message);
+                true
+            }),
+        );
+
+        let date = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        service.emit(BusEvent::EntrySaved(date));
+        service.emit(BusEvent::ReminderDue(
+            Prompt::Morning,
+            date.and_hms_opt(9, 0, 0).unwrap(),
+        ));
+
+        let signals = tokio::task::spawn_blocking(move || {
+            let mut signals = Vec::new();
+            let deadline = std::time::Instant::now() + CALL_TIMEOUT;
+            while signals.len() < 2 && std::time::Instant::now() < deadline {
+                client.process(EVENT_INTERVAL).unwrap();
+                for message in received.try_iter() {
+                    let member = message.member().unwrap().to_string();
+                    let args: Vec<String> = message
+                        .iter_init()
+                        .filter_map(|arg| arg.as_str().map(str::to_string))
+                        .collect();
+                    signals.push((member, args));
+                }
+            }
+            signals
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            signals,
+            vec![
+                ("EntrySaved".to_string(), vec!["2024-03-05".to_string()]),
+                (
+                    "ReminderDue".to_string(),
+                    vec![
+                        Prompt::Morning.to_string(),
+                        "2024-03-05T09:00:00".to_string()
+                    ]
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,237 @@
+// capture.rs
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use regex::Regex;
+use std::error::Error;
+use tui_textarea::TextArea;
+
+use crate::editor::Cursor;
+
+/// Lines given to the note input, the entry gets the rest of the screen.
+const NOTE_HEIGHT: u16 = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Focus {
+    Note,
+    Tasks,
+}
+
+/// The built-in capture window: the entry so far on top, read-only apart from its task
+/// checkboxes, and an input for the new section below.
+struct Capture<'a> {
+    entry: Vec<String>,
+    /// Lines of `entry` holding a `- [ ]` task
+    tasks: Vec<usize>,
+    selected: usize,
+    focus: Focus,
+    note: TextArea<'a>,
+    /// Ctrl+C was pressed, the note goes if it's pressed again or answered with `y`
+    discarding: bool,
+}
+
+/// Captures a note in the current terminal. Everything in `content` before `cursor` is
+/// shown read-only, the rest is what the input starts with.
+///
+/// Returns the updated content, or an error when the note was thrown away.
+pub fn capture(content: &str, cursor: Option<Cursor>) -> Result<String, Box<dyn Error>> {
+    let mut capture = Capture::new(content, cursor);
+    let mut terminal = ratatui::try_init()?;
+    let saved = capture.run(&mut terminal);
+    ratatui::restore();
+    if !saved? {
+        return Err("the note was discarded in the capture window".into());
+    }
+    Ok(capture.content(content, cursor))
+}
+
+impl Capture<'_> {
+    fn new(content: &str, cursor: Option<Cursor>) -> Self {
+        let lines: Vec<String> = content.lines().map(str::to_string).collect();
+        let split = cursor.map_or(lines.len(), |cursor| (cursor.line - 1).min(lines.len()));
+        let (entry, note) = lines.split_at(split);
+
+        let task = Regex::new(r"^\s*[-*] \[[ xX]\]").unwrap();
+        let tasks = (0..entry.len())
+            .filter(|&i| task.is_match(&entry[i]))
+            .collect();
+        let mut note = TextArea::new(note.to_vec());
+        note.set_block(Block::default().borders(Borders::ALL).title(" New note "));
+        note.set_cursor_line_style(Style::default());
+        note.move_cursor(tui_textarea::CursorMove::Bottom);
+        note.move_cursor(tui_textarea::CursorMove::End);
+        Capture {
+            entry: entry.to_vec(),
+            tasks,
+            selected: 0,
+            focus: Focus::Note,
+            note,
+            discarding: false,
+        }
+    }
+
+    /// The entry with its ticked tasks and the note put back together, `content` and
+    /// `cursor` being what the window was opened with.
+    fn content(&self, content: &str, cursor: Option<Cursor>) -> String {
+        let mut updated = self.entry.join("\n");
+        let note = self.note.lines().join("\n");
+        if cursor.is_some() || !note.trim().is_empty() {
+            updated.push('\n');
+            updated.push_str(&note);
+        }
+        if content.ends_with('\n') && !updated.ends_with('\n') {
+            updated.push('\n');
+        }
+        updated
+    }
+
+    /// Handles keys until the user saves (`true`) or discards the note (`false`).
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<bool, Box<dyn Error>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            if self.discarding {
+                match key.code {
+                    KeyCode::Char('y') | KeyCode::Char('Y') => return Ok(false),
+                    KeyCode::Char('c') if ctrl => return Ok(false),
+                    _ => self.discarding = false,
+                }
+                continue;
+            }
+            match key.code {
+                // Closing the window keeps what was written
+                KeyCode::Char('s') if ctrl => return Ok(true),
+                KeyCode::Esc => return Ok(true),
+                KeyCode::Char('c') if ctrl => self.discarding = true,
+                KeyCode::Tab if !self.tasks.is_empty() => {
+                    self.focus = match self.focus {
+                        Focus::Note => Focus::Tasks,
+                        Focus::Tasks => Focus::Note,
+                    };
+                }
+                _ if self.focus == Focus::Note => {
+                    self.note.input(key);
+                }
+                KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.selected = (self.selected + 1).min(self.tasks.len() - 1)
+                }
+                KeyCode::Char(' ') | KeyCode::Enter => self.toggle(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Ticks the selected task, or unticks it.
+    fn toggle(&mut self) {
+        let line = &mut self.entry[self.tasks[self.selected]];
+        // Task lines start with the checkbox, so the first bracket is its own
+        let Some(at) = line.find('[') else { return };
+        let mark = if &line[at..at + 3] == "[ ]" { "x" } else { " " };
+        line.replace_range(at + 1..at + 2, mark);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [entry_area, note_area, help_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(NOTE_HEIGHT),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let selected = match self.focus {
+            Focus::Tasks => self.tasks.get(self.selected).copied(),
+            Focus::Note => None,
+        };
+        let lines: Vec<Line> = self
+            .entry
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let line = Line::from(line.as_str());
+                if Some(i) == selected {
+                    line.style(Style::default().add_modifier(Modifier::REVERSED))
+                } else {
+                    line
+                }
+            })
+            .collect();
+        // Keep the selected task in view, otherwise the end of the entry
+        let height = entry_area.height.saturating_sub(2) as usize;
+        let focus_line = selected.unwrap_or(self.entry.len().saturating_sub(1));
+        let scroll = (focus_line + 1).saturating_sub(height) as u16;
+        let entry = Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" Today's entry "),
+            )
+            .scroll((scroll, 0));
+        frame.render_widget(entry, entry_area);
+        frame.render_widget(&self.note, note_area);
+
+        let help = match self.focus {
+            _ if self.discarding => "Discard the note? y to discard, any other key to go on",
+            Focus::Note if self.tasks.is_empty() => "Esc save · Ctrl+C discard",
+            Focus::Note => "Esc save · Ctrl+C discard · Tab tasks",
+            Focus::Tasks => "Esc save · Ctrl+C discard · Space toggle · Tab note",
+        };
+        frame.render_widget(Paragraph::new(help), help_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = "# Tasks for today\n- [ ] Call the bank\n- [x] Workout\n\n## 14:00\n";
+
+    #[test]
+    fn splits_the_entry_from_the_new_note() {
+        let cursor = Cursor::end_of(ENTRY);
+        let capture = Capture::new(ENTRY, Some(cursor));
+        assert_eq!(
+            capture.entry,
+            [
+                "# Tasks for today",
+                "- [ ] Call the bank",
+                "- [x] Workout",
+                ""
+            ]
+        );
+        assert_eq!(capture.note.lines(), ["## 14:00"]);
+        assert_eq!(capture.tasks, [1, 2]);
+        // Nothing typed gives back what it was opened with
+        assert_eq!(capture.content(ENTRY, Some(cursor)), ENTRY);
+    }
+
+    #[test]
+    fn adds_the_note_below_the_entry() {
+        let mut capture = Capture::new("Morning\n", None);
+        assert_eq!(capture.content("Morning\n", None), "Morning\n");
+        capture.note.insert_str("A thought");
+        assert_eq!(capture.content("Morning\n", None), "Morning\nA thought\n");
+    }
+
+    #[test]
+    fn toggles_the_selected_task() {
+        let cursor = Some(Cursor::end_of(ENTRY));
+        let mut capture = Capture::new(ENTRY, cursor);
+        capture.toggle();
+        capture.selected = 1;
+        capture.toggle();
+        assert_eq!(
+            capture.content(ENTRY, cursor),
+            "# Tasks for today\n- [x] Call the bank\n- [ ] Workout\n\n## 14:00\n"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+// carry_over.rs
+use chrono::NaiveDate;
+use diary_app::{CarryOverConfig, CarryOverMode};
+
+use crate::task_manager::parse_tasks;
+
+/// The tasks left open in the entry for `date`, a `- [ ]` line each ending in the day
+/// it was first written down. Tasks `template` already lists aren't repeated.
+pub fn carried_tasks(
+    config: &CarryOverConfig,
+    date: NaiveDate,
+    entry: &str,
+    template: &str,
+) -> String {
+    if config.mode == CarryOverMode::Off {
+        return String::new();
+    }
+    let planned: Vec<String> = parse_tasks(date, template)
+        .into_iter()
+        .map(|task| task.text)
+        .collect();
+    let tag = config.tag.trim_start_matches('#').to_string();
+    let mut carried = Vec::new();
+    let mut lines = String::new();
+    for task in parse_tasks(date, entry) {
+        if task.done
+            || (config.mode == CarryOverMode::Tagged && !task.tags.contains(&tag))
+            || planned.contains(&task.text)
+            || carried.contains(&task.text)
+        {
+            continue;
+        }
+        // A task carried before keeps the date it started on
+        lines.push_str(&format!(
+            "- [ ] {} (from {})\n",
+            task.text,
+            task.created.format("%Y-%m-%d")
+        ));
+        carried.push(task.text);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(mode: CarryOverMode) -> CarryOverConfig {
+        CarryOverConfig {
+            mode,
+            ..CarryOverConfig::default()
+        }
+    }
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, 5).unwrap()
+    }
+
+    #[test]
+    fn annotates_with_the_original_date() {
+        let entry = "- [ ] Call the bank\n- [ ] Renew passport (from 2024-02-28)\n";
+        assert_eq!(
+            carried_tasks(&config(CarryOverMode::All), date(), entry, ""),
+            "- [ ] Call the bank (from 2024-03-05)\n- [ ] Renew passport (from 2024-02-28)\n"
+        );
+    }
+
+    #[test]
+    fn skips_tasks_the_template_has_and_duplicates() {
+        let entry = "- [ ] Workout\n- [ ] Call the bank\n- [ ] Call the bank (from 2024-03-01)\n";
+        let template = "# Tasks for today\n- [ ] Workout\n";
+        assert_eq!(
+            carried_tasks(&config(CarryOverMode::All), date(), entry, template),
+            "- [ ] Call the bank (from 2024-03-05)\n"
+        );
+    }
+
+    #[test]
+    fn leaves_done_tasks() {
+        let entry = "- [x] Workout\n- [X] Read done:2024-03-05\n- [ ] Call the bank\n";
+        assert_eq!(
+            carried_tasks(&config(CarryOverMode::All), date(), entry, ""),
+            "- [ ] Call the bank (from 2024-03-05)\n"
+        );
+    }
+
+    #[test]
+    fn follows_the_mode() {
+        let entry = "- [ ] Call the bank #carry\n- [ ] Water the plants\n- [ ] Fix #carryover\n";
+        assert_eq!(
+            carried_tasks(&config(CarryOverMode::Tagged), date(), entry, ""),
+            "- [ ] Call the bank #carry (from 2024-03-05)\n"
+        );
+        assert_eq!(
+            carried_tasks(&config(CarryOverMode::Off), date(), entry, ""),
+            ""
+        );
+    }
+}
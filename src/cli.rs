@@ -0,0 +1,742 @@
+// cli.rs
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use diary_app::{
+    day_mood, Config, Entry, EntryMetadata, Section, Storage, StorageType, CITY_PREFIX,
+    WEATHER_PREFIX,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ConfigManager;
+use crate::control::{self, ControlCommand};
+use crate::daemon::with_note;
+use crate::editor::{launch_editor, Cursor};
+use crate::habits::{checked, habit_stats, heatmap};
+use crate::scheduler::Scheduler;
+use crate::storage::sqlite::SqliteStorage;
+use crate::task_manager::{parse_task_id, Task, TaskManager};
+use crate::{build_backend, build_storage, entry_history};
+
+/// Longest preview `list` shows of an entry.
+const PREVIEW_LEN: usize = 60;
+/// Days the `habits` and `stats` reports cover without `--from`.
+const REPORT_DAYS: i64 = 28;
+/// Characters a point on the 1 to 5 scale takes up in a `stats` bar.
+const BAR_WIDTH_PER_POINT: f64 = 4.0;
+
+/// An entry as written by `export` and read by `import`.
+#[derive(Serialize, Deserialize)]
+struct ExportedEntry {
+    date: NaiveDate,
+    content: String,
+    /// Left out of Markdown exports, worked out from the content again on import
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<EntryMetadata>,
+}
+
+/// What `stats` charts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsMetric {
+    Mood,
+    Energy,
+}
+
+/// Which check-ins of an entry `show` prints.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionFilter {
+    /// The one that was being written at this time
+    At(NaiveTime),
+    /// Those started within this long of now
+    Since(Duration),
+}
+
+/// How `export` writes the entries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// One JSON array of `{ "date", "content", "metadata" }` objects
+    Json,
+    /// A `YYYY-MM-DD.md` file per entry
+    Markdown,
+}
+
+/// Today's diary date, which only moves on at the configured day start.
+fn today(config: &Config) -> NaiveDate {
+    Scheduler::new(config).logical_date()
+}
+
+/// Prints the entry for `date`, today's when `None`, or only the check-ins `sections`
+/// picks out. Its metadata goes on top as YAML when `metadata` is set.
+pub async fn run_show(
+    config: &Config,
+    date: Option<NaiveDate>,
+    sections: Option<SectionFilter>,
+    metadata: bool,
+) -> Result<(), Box<dyn Error>> {
+    let date = date.unwrap_or_else(|| today(config));
+    let Some(entry) = build_storage(config)?.get_entry_with_metadata(date).await? else {
+        eprintln!("No entry for {}", date);
+        return Ok(());
+    };
+    if metadata {
+        print!("---\n{}---\n", serde_yaml::to_string(&entry.metadata)?);
+    }
+    let Some(filter) = sections else {
+        print!("{}", entry.body);
+        return Ok(());
+    };
+
+    // Check-ins before the day start were written after midnight
+    let on_day = |start: NaiveDateTime| {
+        if start.date() == date && start.time() < config.day_start_time {
+            start + Duration::days(1)
+        } else {
+            start
+        }
+    };
+    let timed: Vec<Section> = entry
+        .sections()
+        .into_iter()
+        .filter_map(|section| {
+            Some(Section {
+                start: Some(on_day(section.start?)),
+                ..section
+            })
+        })
+        .collect();
+    let picked: Vec<&Section> = match filter {
+        SectionFilter::At(time) => {
+            let at = on_day(date.and_time(time));
+            timed
+                .iter()
+                .rev()
+                .find(|section| section.start.is_some_and(|start| start <= at))
+                .into_iter()
+                .collect()
+        }
+        SectionFilter::Since(duration) => {
+            let cutoff = Local::now().naive_local() - duration;
+            timed
+                .iter()
+                .filter(|section| section.start.is_some_and(|start| start >= cutoff))
+                .collect()
+        }
+    };
+    if picked.is_empty() {
+        eprintln!("No matching check-ins in the entry for {}", date);
+    }
+    for section in picked {
+        let start = section.start.expect("only timed sections are picked");
+        match section.since_previous {
+            Some(gap) => println!("## {} (+{})", start.format("%H:%M"), format_duration(gap)),
+            None => println!("## {}", start.format("%H:%M")),
+        }
+        println!("\n{}\n", section.body);
+    }
+    Ok(())
+}
+
+/// A duration as `2h 15m`, or `40m` under an hour.
+fn format_duration(duration: Duration) -> String {
+    let (hours, minutes) = (duration.num_hours(), duration.num_minutes() % 60);
+    if hours == 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h {}m", hours, minutes)
+    }
+}
+
+/// Prints a line per entry between `from` and `to`: date, length and how it starts.
+pub async fn run_list(
+    config: &Config,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<(), Box<dyn Error>> {
+    let storage = build_storage(config)?;
+    for date in entries_between(storage.as_ref(), from, to).await? {
+        let content = storage.get_entry(date).await?.unwrap_or_default();
+        println!(
+            "{}  {:>5} words  {}",
+            date,
+            content.split_whitespace().count(),
+            preview(&content)
+        );
+    }
+    Ok(())
+}
+
+async fn entries_between(
+    storage: &dyn Storage,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+    Ok(storage
+        .list_entries()
+        .await?
+        .into_iter()
+        .filter(|date| from.is_none_or(|from| *date >= from))
+        .filter(|date| to.is_none_or(|to| *date <= to))
+        .collect())
+}
+
+/// The first line of an entry worth reading, past the city and weather header.
+fn preview(content: &str) -> String {
+    let line = content
+        .lines()
+        .map(str::trim)
+        .find(|line| {
+            !line.is_empty() && !line.starts_with(CITY_PREFIX) && !line.starts_with(WEATHER_PREFIX)
+        })
+        .unwrap_or_default();
+    if line.chars().count() > PREVIEW_LEN {
+        format!(
+            "{}…",
+            line.chars().take(PREVIEW_LEN - 1).collect::<String>()
+        )
+    } else {
+        line.to_string()
+    }
+}
+
+/// Opens the editor on the entry for `date`. Without a date the running instance is
+/// asked to prompt, when there is one, so it keeps track of the check-in.
+pub async fn run_open(config: &Config, date: Option<NaiveDate>) -> Result<(), Box<dyn Error>> {
+    if date.is_none() {
+        if let Some(reply) = control::send(ControlCommand::Open).await? {
+            println!("{}", reply);
+            return Ok(());
+        }
+    }
+
+    let date = date.unwrap_or_else(|| today(config));
+    let storage = build_storage(config)?;
+    let content = storage.get_entry(date).await?.unwrap_or_default();
+    let cursor = (!content.is_empty()).then(|| Cursor::end_of(&content));
+    // Markers have nothing to reschedule without the daemon, they're just dropped
+    launch_editor(storage.as_ref(), &config.editor, date, content, cursor).await?;
+    Ok(())
+}
+
+/// Adds `note` to today's entry, through the running instance when there is one.
+pub async fn run_add(config: &Config, note: String) -> Result<(), Box<dyn Error>> {
+    if note.trim().is_empty() {
+        return Err("The note is empty".into());
+    }
+    if let Some(reply) = control::send(ControlCommand::Append(note.clone())).await? {
+        println!("{}", reply);
+        return Ok(());
+    }
+
+    let date = today(config);
+    let storage = build_storage(config)?;
+    let entry = storage.get_entry(date).await?;
+    storage.save_entry(date, &with_note(entry, &note)).await?;
+    println!("Note added to {}", date);
+    Ok(())
+}
+
+/// Writes the entries between `from` and `to` to `output`, JSON goes to stdout without one.
+pub async fn run_export(
+    config: &Config,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    format: ExportFormat,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let storage = build_storage(config)?;
+    let mut entries = Vec::new();
+    for date in entries_between(storage.as_ref(), from, to).await? {
+        if let Some(entry) = storage.get_entry_with_metadata(date).await? {
+            entries.push(ExportedEntry {
+                date,
+                content: entry.body,
+                metadata: Some(entry.metadata),
+            });
+        }
+    }
+
+    match (format, output) {
+        (ExportFormat::Json, None) => println!("{}", serde_json::to_string_pretty(&entries)?),
+        (ExportFormat::Json, Some(output)) => {
+            fs::write(output, serde_json::to_string_pretty(&entries)?)?;
+        }
+        (ExportFormat::Markdown, None) => {
+            return Err("Markdown export needs a folder to write the entries to".into());
+        }
+        (ExportFormat::Markdown, Some(output)) => {
+            fs::create_dir_all(output)?;
+            for entry in &entries {
+                fs::write(output.join(format!("{}.md", entry.date)), &entry.content)?;
+            }
+        }
+    }
+    if output.is_some() {
+        println!("Exported {} entries", entries.len());
+    }
+    Ok(())
+}
+
+/// Saves the entries from an `export` JSON file, or a folder of `YYYY-MM-DD.md` files.
+/// Dates that already have an entry are left alone unless `overwrite` is set.
+pub async fn run_import(
+    config: &Config,
+    input: &Path,
+    overwrite: bool,
+) -> Result<(), Box<dyn Error>> {
+    let entries = if input.is_dir() {
+        let mut entries = Vec::new();
+        for file in fs::read_dir(input)?.filter_map(Result::ok) {
+            let path = file.path();
+            let date = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+            match date {
+                Some(date) if path.extension().is_some_and(|ext| ext == "md") => {
+                    entries.push(ExportedEntry {
+                        date,
+                        content: fs::read_to_string(&path)?,
+                        metadata: None,
+                    });
+                }
+                _ => eprintln!("Skipping {}, not named YYYY-MM-DD.md", path.display()),
+            }
+        }
+        entries.sort_by_key(|entry| entry.date);
+        entries
+    } else {
+        serde_json::from_str::<Vec<ExportedEntry>>(&fs::read_to_string(input)?)?
+    };
+
+    // Saving through the history wrapper keeps anything overwritten as a revision
+    let storage = build_storage(config)?;
+    let (mut imported, mut skipped) = (0, 0);
+    for entry in entries {
+        if !overwrite && storage.get_entry(entry.date).await?.is_some() {
+            skipped += 1;
+            continue;
+        }
+        match entry.metadata {
+            Some(metadata) => {
+                let entry = Entry {
+                    date: entry.date,
+                    body: entry.content,
+                    metadata,
+                };
+                storage.save_entry_with_metadata(&entry).await?;
+            }
+            None => storage.save_entry(entry.date, &entry.content).await?,
+        }
+        imported += 1;
+    }
+    println!("Imported {} entries", imported);
+    if skipped > 0 {
+        println!(
+            "Skipped {} dates that already have an entry, use --overwrite to replace them",
+            skipped
+        );
+    }
+    Ok(())
+}
+
+/// Prints the tasks in the entries between `from` and `to`, only the open ones unless
+/// `all` is set.
+pub async fn run_tasks_list(
+    config: &Config,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    tag: Option<&str>,
+    all: bool,
+) -> Result<(), Box<dyn Error>> {
+    let storage = build_storage(config)?;
+    let tag = tag.map(|tag| tag.trim_start_matches('#').to_string());
+    let tasks: Vec<Task> = TaskManager::new(storage.as_ref())
+        .tasks(from, to)
+        .await?
+        .into_iter()
+        .filter(|task| all || !task.done)
+        // Habits have a report of their own
+        .filter(|task| {
+            !config
+                .habits
+                .names
+                .iter()
+                .any(|habit| task.text.eq_ignore_ascii_case(habit))
+        })
+        .filter(|task| tag.as_ref().is_none_or(|tag| task.tags.contains(tag)))
+        .collect();
+    if tasks.is_empty() {
+        eprintln!("No tasks");
+    }
+
+    let today = today(config);
+    for task in tasks {
+        let mut notes = Vec::new();
+        if let Some(due) = task.due.filter(|_| !task.done) {
+            let overdue = if due < today { ", overdue" } else { "" };
+            notes.push(format!("due {}{}", due, overdue));
+        }
+        match task.completed {
+            Some(completed) => notes.push(format!("done {}", completed)),
+            None if task.created < task.date => notes.push(format!("open since {}", task.created)),
+            None => {}
+        }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!("  ({})", notes.join(", "))
+        };
+        let mark = if task.done { "x" } else { " " };
+        println!("{:<13} [{}] {}{}", task.id(), mark, task.text, notes);
+    }
+    Ok(())
+}
+
+/// Ticks tasks off in the entries they're in. `targets` are task ids, or words from
+/// the text of one open task.
+pub async fn run_tasks_done(config: &Config, targets: Vec<&str>) -> Result<(), Box<dyn Error>> {
+    let storage = build_storage(config)?;
+    let manager = TaskManager::new(storage.as_ref());
+    let ids: Option<Vec<(NaiveDate, usize)>> =
+        targets.iter().map(|target| parse_task_id(target)).collect();
+    let ids = match ids {
+        Some(ids) => ids,
+        None => vec![find_open_task(&manager, &targets.join(" ")).await?],
+    };
+    for (date, number) in ids {
+        let task = manager.complete(date, number, today(config)).await?;
+        println!("Done: {}", task.text);
+    }
+    Ok(())
+}
+
+/// The id of the one open task whose text has `words` in it.
+async fn find_open_task(
+    manager: &TaskManager<'_>,
+    words: &str,
+) -> Result<(NaiveDate, usize), Box<dyn Error>> {
+    let words = words.to_lowercase();
+    let matches: Vec<Task> = manager
+        .tasks(None, None)
+        .await?
+        .into_iter()
+        .filter(|task| !task.done && task.text.to_lowercase().contains(&words))
+        .collect();
+    match matches.as_slice() {
+        [task] => Ok((task.date, task.number)),
+        [] => Err(format!("No open task matches `{}`", words).into()),
+        _ => {
+            let ids: Vec<String> = matches
+                .iter()
+                .map(|task| format!("  {}  {}", task.id(), task.text))
+                .collect();
+            Err(format!(
+                "`{}` matches more than one task, pick one by id:\n{}",
+                words,
+                ids.join("\n")
+            )
+            .into())
+        }
+    }
+}
+
+/// Adds an open task to today's entry.
+pub async fn run_tasks_add(
+    config: &Config,
+    text: &str,
+    due: Option<NaiveDate>,
+) -> Result<(), Box<dyn Error>> {
+    let text = match due {
+        Some(due) => format!("{} due:{}", text.trim(), due),
+        None => text.to_string(),
+    };
+    let storage = build_storage(config)?;
+    let task = TaskManager::new(storage.as_ref())
+        .add(today(config), &text)
+        .await?;
+    println!("Added {}", task.id());
+    Ok(())
+}
+
+/// Prints streaks, completion and a heatmap for each habit, over the four weeks up to
+/// today unless `from` and `to` say otherwise.
+pub async fn run_habits(
+    config: &Config,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<(), Box<dyn Error>> {
+    let habits = &config.habits.names;
+    if habits.is_empty() {
+        println!("No habits configured, add some with `diary_app config set habits.names '[\"Workout\"]'`");
+        return Ok(());
+    }
+    let (from, to) = report_range(config, from, to)?;
+    let entries = entries_in(config, from, to).await?;
+    let dates: Vec<NaiveDate> = from.iter_days().take_while(|date| *date <= to).collect();
+    let ends_today = to == today(config);
+
+    println!(
+        "Habits from {} to {}, ■ done □ not done · no entry",
+        from, to
+    );
+    let width = habits
+        .iter()
+        .map(|habit| habit.chars().count())
+        .max()
+        .unwrap_or(0);
+    println!("{:<width$}  Streak  Best  Done", "", width = width);
+    for habit in habits {
+        let days: Vec<Option<bool>> = dates
+            .iter()
+            .map(|date| {
+                entries
+                    .iter()
+                    .find(|(entry_date, _)| entry_date == date)
+                    .and_then(|(_, content)| checked(content, habit))
+            })
+            .collect();
+        let stats = habit_stats(&days, ends_today);
+        let rate = (stats.done * 100)
+            .checked_div(stats.tracked)
+            .map_or("   -".to_string(), |rate| format!("{:>3}%", rate));
+        println!(
+            "{:<width$}  {:>6}  {:>4}  {:>5} {}  {}",
+            habit,
+            stats.current_streak,
+            stats.best_streak,
+            format!("{}/{}", stats.done, stats.tracked),
+            rate,
+            heatmap(&days),
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// The days a report covers, the last four weeks unless `from` and `to` say otherwise.
+fn report_range(
+    config: &Config,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<(NaiveDate, NaiveDate), Box<dyn Error>> {
+    let to = to.unwrap_or_else(|| today(config));
+    let from = from.unwrap_or(to - Duration::days(REPORT_DAYS - 1));
+    if from > to {
+        return Err("--from is after --to".into());
+    }
+    Ok((from, to))
+}
+
+/// The entries between `from` and `to` with their content, oldest first.
+async fn entries_in(
+    config: &Config,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<(NaiveDate, String)>, Box<dyn Error>> {
+    let storage = build_storage(config)?;
+    let mut entries = Vec::new();
+    for date in entries_between(storage.as_ref(), Some(from), Some(to)).await? {
+        if let Some(content) = storage.get_entry(date).await? {
+            entries.push((date, content));
+        }
+    }
+    Ok(entries)
+}
+
+/// Charts the daily average mood or energy, over the four weeks up to today unless
+/// `from` and `to` say otherwise.
+pub async fn run_stats(
+    config: &Config,
+    metric: StatsMetric,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<(), Box<dyn Error>> {
+    let (from, to) = report_range(config, from, to)?;
+    let entries = entries_in(config, from, to).await?;
+    let days: Vec<(NaiveDate, Option<f64>)> = from
+        .iter_days()
+        .take_while(|date| *date <= to)
+        .map(|date| {
+            let value = entries
+                .iter()
+                .find(|(entry_date, _)| *entry_date == date)
+                .and_then(|(_, content)| {
+                    let mood = day_mood(content);
+                    match metric {
+                        StatsMetric::Mood => mood.mood,
+                        StatsMetric::Energy => mood.energy,
+                    }
+                });
+            (date, value)
+        })
+        .collect();
+
+    let name = match metric {
+        StatsMetric::Mood => "Mood",
+        StatsMetric::Energy => "Energy",
+    };
+    let values: Vec<f64> = days.iter().filter_map(|(_, value)| *value).collect();
+    if values.is_empty() {
+        println!(
+            "No {} recorded from {} to {}, write e.g. `mood: 4 energy: 2` in a check-in",
+            name.to_lowercase(),
+            from,
+            to
+        );
+        return Ok(());
+    }
+    let average = values.iter().sum::<f64>() / values.len() as f64;
+    println!(
+        "{} from {} to {}, averaging {:.1} over {} days",
+        name,
+        from,
+        to,
+        average,
+        values.len()
+    );
+    for (date, value) in days {
+        match value {
+            Some(value) => println!(
+                "{}  {:<20}  {:.1}",
+                date.format("%Y-%m-%d %a"),
+                "█".repeat((value * BAR_WIDTH_PER_POINT).round() as usize),
+                value
+            ),
+            None => println!("{}  ·", date.format("%Y-%m-%d %a")),
+        }
+    }
+    Ok(())
+}
+
+/// Brings the local SQLite index and the configured storage back in step.
+pub async fn run_sync(config: &Config) -> Result<(), Box<dyn Error>> {
+    if matches!(config.storage_type, StorageType::Sqlite) || !config.sqlite_index {
+        println!("Nothing to sync, entries are only kept in one place");
+        return Ok(());
+    }
+    let index = SqliteStorage::with_backend(
+        ConfigManager::data_dir().join("diary.sqlite3"),
+        build_backend(config)?,
+    )?;
+    let report = index.sync().await?;
+    println!(
+        "Sent {} entries saved while offline, updated {} from storage",
+        report.pushed, report.pulled
+    );
+    Ok(())
+}
+
+pub fn run_history(
+    config: &Config,
+    date: NaiveDate,
+    rev: Option<usize>,
+) -> Result<(), Box<dyn Error>> {
+    let history = entry_history(config);
+    if let Some(rev) = rev {
+        print!("{}", history.revision(date, rev)?.content()?);
+        return Ok(());
+    }
+
+    let revisions = history.revisions(date)?;
+    if revisions.is_empty() {
+        println!("No earlier revisions of {}", date);
+        return Ok(());
+    }
+    println!("Revisions of {}, newest first:", date);
+    for revision in revisions {
+        let content = revision.content()?;
+        println!(
+            "  rev {:<3} saved {}  {} lines, {} bytes",
+            revision.number,
+            revision.saved_at.format("%Y-%m-%d %H:%M:%S"),
+            content.lines().count(),
+            content.len()
+        );
+    }
+    Ok(())
+}
+
+pub async fn run_restore(
+    config: &Config,
+    date: NaiveDate,
+    rev: usize,
+) -> Result<(), Box<dyn Error>> {
+    let content = entry_history(config).revision(date, rev)?.content()?;
+    // Saving through the history wrapper keeps the current content as a revision too
+    build_storage(config)?.save_entry(date, &content).await?;
+    println!("Restored {} to revision {}", date, rev);
+    Ok(())
+}
+
+/// Settings holding credentials, only printed when asked for by name.
+const SECRET_SETTINGS: &[&str] = &["notion_token", "google_drive_token"];
+
+/// Prints a setting, `key` is a dotted path like `editor.command`. The whole
+/// configuration is printed without one, with the secrets in it hidden.
+pub fn run_config_get(config: &Config, key: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut settings = serde_json::to_value(config)?;
+    if key.is_none() {
+        for secret in SECRET_SETTINGS {
+            if let Some(value) = settings.get_mut(*secret).filter(|value| !value.is_null()) {
+                *value = Value::String(format!("(hidden, `config get {}` shows it)", secret));
+            }
+        }
+    }
+    let value = match key {
+        Some(key) => setting(&settings, key)?,
+        None => &settings,
+    };
+    match value {
+        Value::String(text) => println!("{}", text),
+        value => println!("{}", serde_json::to_string_pretty(value)?),
+    }
+    Ok(())
+}
+
+/// Changes a setting and tells the running instance. `value` is read as JSON, or taken
+/// as plain text when that doesn't fit the setting.
+pub async fn run_config_set(config: &Config, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    let mut candidates = Vec::new();
+    if let Ok(json) = serde_json::from_str::<Value>(value) {
+        candidates.push(json);
+    }
+    candidates.push(Value::String(value.to_string()));
+
+    let mut last_error = None;
+    for candidate in candidates {
+        let mut settings = serde_json::to_value(config)?;
+        *setting_mut(&mut settings, key)? = candidate;
+        match serde_json::from_value::<Config>(settings) {
+            Ok(updated) => {
+                ConfigManager::save(&updated)?;
+                println!("Set {}", key);
+                if let Some(reply) = control::send(ControlCommand::Reload).await? {
+                    println!("{}", reply);
+                }
+                return Ok(());
+            }
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(format!(
+        "`{}` isn't a valid value for {}: {}",
+        value,
+        key,
+        last_error.expect("a candidate was tried")
+    )
+    .into())
+}
+
+fn setting<'a>(settings: &'a Value, key: &str) -> Result<&'a Value, Box<dyn Error>> {
+    key.split('.')
+        .try_fold(settings, |value, part| value.as_object()?.get(part))
+        .ok_or_else(|| format!("Unknown setting `{}`", key).into())
+}
+
+fn setting_mut<'a>(settings: &'a mut Value, key: &str) -> Result<&'a mut Value, Box<dyn Error>> {
+    key.split('.')
+        .try_fold(settings, |value, part| value.as_object_mut()?.get_mut(part))
+        .ok_or_else(|| format!("Unknown setting `{}`", key).into())
+}
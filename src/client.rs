@@ -0,0 +1,66 @@
+// client.rs
+use std::error::Error;
+
+use crate::config::ConfigManager;
+use crate::control::{self, ClientMessage, EditReply};
+use crate::editor::{edit, EditOutcome};
+
+/// How long to wait before looking for the daemon again.
+const RECONNECT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// The foreground half of the app, run inside the desktop session.
+///
+/// Attaches to the running instance and opens the editor whenever a prompt is due, so
+/// the daemon itself can run headless, e.g. as a systemd user service. Keeps waiting
+/// for the daemon when it isn't running or restarts.
+pub async fn run_client() -> Result<(), Box<dyn Error>> {
+    let mut announced = false;
+    loop {
+        match control::attach().await? {
+            Some(mut session) => {
+                println!("Attached to Diary App, waiting for prompts");
+                announced = false;
+                loop {
+                    let message = match session.next().await {
+                        Ok(Some(message)) => message,
+                        Ok(None) => break,
+                        Err(err) => {
+                            eprintln!("Lost Diary App: {}", err);
+                            break;
+                        }
+                    };
+                    match message {
+                        ClientMessage::Edit(request) => {
+                            println!("Editing the entry for {}", request.date);
+                            // Read every time, so editor changes apply without a restart
+                            let editor = ConfigManager::load()
+                                .ok()
+                                .flatten()
+                                .map(|config| config.editor)
+                                .unwrap_or_default();
+                            let reply = match edit(&editor, &request.content, request.cursor) {
+                                Ok((outcome, action)) => EditReply { outcome, action },
+                                Err(err) => EditReply {
+                                    outcome: EditOutcome::Aborted(err.to_string()),
+                                    action: None,
+                                },
+                            };
+                            if let Err(err) = session.reply(&reply).await {
+                                eprintln!("Couldn't hand the edit back: {}", err);
+                                break;
+                            }
+                        }
+                        ClientMessage::Refused(reason) => return Err(reason.into()),
+                    }
+                }
+                println!("Diary App went away, waiting for it to come back");
+            }
+            None if !announced => {
+                println!("Diary App isn't running, waiting for it");
+                announced = true;
+            }
+            None => {}
+        }
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+    }
+}
@@ -2,22 +2,64 @@
 use diary_app::Config;
 use directories::ProjectDirs;
 use std::error::Error;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
 
 pub struct ConfigManager;
 
 impl ConfigManager {
+    fn project_dirs() -> ProjectDirs {
+        ProjectDirs::from("com", "ravinderpayal.os", "DiaryApp")
+            .expect("Failed to get project directories")
+    }
+
     fn config_path() -> PathBuf {
-        let proj_dirs = ProjectDirs::from("com", "ravinderpayal.os", "DiaryApp")
-            .expect("Failed to get project directories");
-        proj_dirs.config_dir().join("config.json")
+        Self::project_dirs().config_dir().join("config.json")
+    }
+
+    /// User-editable entry templates, see `templates` in the config
+    pub fn templates_dir() -> PathBuf {
+        Self::project_dirs().config_dir().join("templates")
+    }
+
+    /// Where the app keeps its own data, such as the SQLite index
+    pub fn data_dir() -> PathBuf {
+        Self::project_dirs().data_dir().to_path_buf()
+    }
+
+    /// Per-session files like the instance lock and control socket, `$XDG_RUNTIME_DIR`
+    /// when there is one. The socket hands out the diary, so the folder has to be the
+    /// user's alone.
+    pub fn runtime_dir() -> Result<PathBuf, Box<dyn Error>> {
+        let dir = match Self::project_dirs().runtime_dir() {
+            Some(dir) => dir.to_path_buf(),
+            None => std::env::temp_dir().join(format!("diaryapp-{}", current_uid())),
+        };
+        Self::ensure_private(&dir)?;
+        Ok(dir)
+    }
+
+    /// Creates `dir` readable by the user only, and refuses one someone else made first
+    /// or that others can get into.
+    fn ensure_private(dir: &Path) -> Result<(), Box<dyn Error>> {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        // Not following symlinks, a link to somewhere else isn't ours either
+        let metadata = fs::symlink_metadata(dir)?;
+        if !metadata.is_dir() || metadata.uid() != current_uid() || metadata.mode() & 0o077 != 0 {
+            return Err(format!(
+                "{} isn't private to this user, remove it or set XDG_RUNTIME_DIR",
+                dir.display()
+            )
+            .into());
+        }
+        Ok(())
     }
 
     pub fn load() -> Result<Option<Config>, Box<dyn Error>> {
         let config_path = Self::config_path();
         if config_path.exists() {
-            println!("Config path exists: {}", config_path.to_str().unwrap());
+            eprintln!("Config path exists: {}", config_path.to_str().unwrap());
             let config_str = fs::read_to_string(config_path)?;
             Ok(Some(serde_json::from_str(&config_str)?))
         } else {
@@ -36,3 +78,8 @@ impl ConfigManager {
         Ok(())
     }
 }
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and can't fail
+    unsafe { libc::getuid() }
+}
@@ -0,0 +1,347 @@
+// control.rs
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::OwnedReadHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::ConfigManager;
+use crate::editor::{Cursor, EditOutcome};
+use crate::scheduler::ReminderAction;
+
+/// How long a client waits for the daemon, which can't answer while an editor is open.
+const REPLY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Commands forwarded to the running instance. On the socket every message is a line
+/// of JSON, so entries and notes can span lines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ControlCommand {
+    Open,
+    Snooze(#[serde(with = "minutes")] Duration),
+    Skip,
+    Reload,
+    Status,
+    /// The entry for a date, today's diary date when `None`
+    Entry(Option<NaiveDate>),
+    /// Adds a timestamped note to today's entry
+    Append(String),
+    /// Turns the connection into an editor client session, see [`EditJob`]
+    Attach,
+    Quit,
+}
+
+/// Durations travel as whole minutes.
+pub mod minutes {
+    use chrono::Duration;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(duration.num_minutes())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let minutes = i64::deserialize(deserializer)?;
+        Duration::try_minutes(minutes)
+            .ok_or_else(|| D::Error::custom(format!("{} minutes is too long", minutes)))
+    }
+}
+
+/// A command received over the socket or D-Bus, answered with a message or an error.
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: oneshot::Sender<Result<String, String>>,
+}
+
+impl ControlRequest {
+    pub fn respond(self, message: impl Into<String>) {
+        // The client may have given up waiting already
+        let _ = self.reply.send(Ok(message.into()));
+    }
+
+    pub fn fail(self, message: impl Into<String>) {
+        let _ = self.reply.send(Err(message.into()));
+    }
+
+    /// Whether the sender stopped waiting, e.g. while an editor kept the daemon busy.
+    /// It has already told the user the request failed, so it's dropped.
+    pub fn is_abandoned(&self) -> bool {
+        self.reply.is_closed()
+    }
+}
+
+/// Hands `command` to the daemon and waits for its answer.
+pub async fn dispatch(
+    sender: &mpsc::Sender<ControlRequest>,
+    command: ControlCommand,
+) -> Result<String, String> {
+    let (reply, answer) = oneshot::channel();
+    sender
+        .send(ControlRequest { command, reply })
+        .await
+        .map_err(|_| "Diary App is shutting down".to_string())?;
+    match tokio::time::timeout(REPLY_TIMEOUT, answer).await {
+        Ok(Ok(reply)) => reply,
+        Ok(Err(_)) => Err("No answer".to_string()),
+        Err(_) => Err("The running instance is busy, is an editor open?".to_string()),
+    }
+}
+
+/// A buffer the daemon wants edited in the user's session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditRequest {
+    pub date: NaiveDate,
+    pub content: String,
+    /// Where to start typing, see [`Cursor`]
+    #[serde(default)]
+    pub cursor: Option<Cursor>,
+}
+
+/// What became of an [`EditRequest`] once the editor closed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EditReply {
+    pub outcome: EditOutcome,
+    pub action: Option<ReminderAction>,
+}
+
+/// Sent to an attached editor client.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Edit(EditRequest),
+    /// The daemon already has a client and won't take this one
+    Refused(String),
+}
+
+/// An edit handed to the attached client, answered once the user is done.
+struct EditJob {
+    request: EditRequest,
+    reply: oneshot::Sender<EditReply>,
+}
+
+/// The daemon's end of an attached editor client.
+pub struct EditorClient {
+    jobs: mpsc::Sender<EditJob>,
+    refusal: oneshot::Sender<String>,
+}
+
+impl EditorClient {
+    /// Whether the client has gone away.
+    pub fn is_closed(&self) -> bool {
+        self.jobs.is_closed()
+    }
+
+    /// Hands `request` to the client, the answer arrives once the user is done. Gives
+    /// `request` back when the client has gone away.
+    pub async fn edit(
+        &self,
+        request: EditRequest,
+    ) -> Result<oneshot::Receiver<EditReply>, EditRequest> {
+        let (reply, answer) = oneshot::channel();
+        match self.jobs.send(EditJob { request, reply }).await {
+            Ok(()) => Ok(answer),
+            Err(mpsc::error::SendError(job)) => Err(job.request),
+        }
+    }
+
+    /// Turns the client away, it stops rather than try again.
+    pub fn refuse(self, reason: impl Into<String>) {
+        let _ = self.refusal.send(reason.into());
+    }
+}
+
+/// Held by the one running instance: a locked PID file and the control socket next to it.
+pub struct InstanceLock {
+    _file: File,
+    socket_path: PathBuf,
+}
+
+impl InstanceLock {
+    fn socket_path() -> Result<PathBuf, Box<dyn Error>> {
+        Ok(ConfigManager::runtime_dir()?.join("diary_app.sock"))
+    }
+
+    /// Takes the lock, or returns `None` when another instance already holds it.
+    pub fn acquire() -> Result<Option<Self>, Box<dyn Error>> {
+        let dir = ConfigManager::runtime_dir()?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join("diary_app.pid"))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+
+        Ok(Some(InstanceLock {
+            _file: file,
+            socket_path: Self::socket_path()?,
+        }))
+    }
+
+    /// Listens on the control socket, passing requests on to `sender` and attached
+    /// editor clients on to `clients`.
+    pub fn serve(
+        &self,
+        sender: mpsc::Sender<ControlRequest>,
+        clients: mpsc::Sender<EditorClient>,
+    ) -> Result<(), Box<dyn Error>> {
+        // Holding the lock means any socket left behind belongs to a dead instance
+        if self.socket_path.exists() {
+            fs::remove_file(&self.socket_path)?;
+        }
+        let listener = UnixListener::bind(&self.socket_path)?;
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let sender = sender.clone();
+                let clients = clients.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_client(stream, sender, clients).await {
+                        eprintln!("Control connection failed: {}", err);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+async fn write_message<T: Serialize>(
+    writer: &mut (impl AsyncWriteExt + Unpin),
+    message: &T,
+) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+async fn read_message<T: for<'de> Deserialize<'de>>(
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+) -> std::io::Result<Option<T>> {
+    match lines.next_line().await? {
+        Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+        None => Ok(None),
+    }
+}
+
+async fn handle_client(
+    stream: UnixStream,
+    sender: mpsc::Sender<ControlRequest>,
+    clients: mpsc::Sender<EditorClient>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let reply = match read_message::<ControlCommand>(&mut lines).await {
+        Ok(Some(ControlCommand::Attach)) => {
+            return serve_editor(lines, writer, clients).await;
+        }
+        Ok(Some(command)) => dispatch(&sender, command).await,
+        Ok(None) => return Ok(()),
+        Err(err) => Err(format!("Invalid request: {}", err)),
+    };
+    Ok(write_message(&mut writer, &reply).await?)
+}
+
+/// Passes edits from the daemon to an attached client and its answers back, until
+/// either side goes away.
+async fn serve_editor(
+    mut lines: Lines<BufReader<OwnedReadHalf>>,
+    mut writer: tokio::net::unix::OwnedWriteHalf,
+    clients: mpsc::Sender<EditorClient>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (jobs_sender, mut jobs) = mpsc::channel(1);
+    let (refusal, mut refused) = oneshot::channel();
+    let client = EditorClient {
+        jobs: jobs_sender,
+        refusal,
+    };
+    if clients.send(client).await.is_err() {
+        return Ok(());
+    }
+    loop {
+        tokio::select! {
+            job = jobs.recv() => {
+                // The daemon is gone, the client will look for the next one
+                let Some(job) = job else { return Ok(()) };
+                write_message(&mut writer, &ClientMessage::Edit(job.request)).await?;
+                match read_message::<EditReply>(&mut lines).await? {
+                    Some(reply) => {
+                        let _ = job.reply.send(reply);
+                    }
+                    None => return Ok(()),
+                }
+            }
+            Ok(reason) = &mut refused => {
+                return Ok(write_message(&mut writer, &ClientMessage::Refused(reason)).await?);
+            }
+        }
+    }
+}
+
+/// Sends `command` to the running instance and returns its answer, `None` when no
+/// instance is listening. A command the instance turns down comes back as an error.
+pub async fn send(command: ControlCommand) -> Result<Option<String>, Box<dyn Error>> {
+    let stream = match UnixStream::connect(InstanceLock::socket_path()?).await {
+        Ok(stream) => stream,
+        Err(_) => return Ok(None),
+    };
+    let (reader, mut writer) = stream.into_split();
+    write_message(&mut writer, &command).await?;
+
+    // The daemon gives up after REPLY_TIMEOUT itself, this only guards against a hung one
+    let mut lines = BufReader::new(reader).lines();
+    let reply: Result<String, String> =
+        tokio::time::timeout(REPLY_TIMEOUT * 2, read_message(&mut lines))
+            .await
+            .map_err(|_| "The running instance isn't answering")??
+            .ok_or("The running instance hung up")?;
+    Ok(Some(reply?))
+}
+
+/// Attaches to the running instance as its editor client, `None` when no instance is
+/// listening. Edits arrive through [`EditorSession::next`].
+pub async fn attach() -> Result<Option<EditorSession>, Box<dyn Error>> {
+    let stream = match UnixStream::connect(InstanceLock::socket_path()?).await {
+        Ok(stream) => stream,
+        Err(_) => return Ok(None),
+    };
+    let (reader, mut writer) = stream.into_split();
+    write_message(&mut writer, &ControlCommand::Attach).await?;
+    Ok(Some(EditorSession {
+        lines: BufReader::new(reader).lines(),
+        writer,
+    }))
+}
+
+/// The client's end of an attached connection.
+pub struct EditorSession {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: tokio::net::unix::OwnedWriteHalf,
+}
+
+impl EditorSession {
+    /// Waits for the daemon's next message, `None` once it has gone away.
+    pub async fn next(&mut self) -> Result<Option<ClientMessage>, Box<dyn Error>> {
+        Ok(read_message(&mut self.lines).await?)
+    }
+
+    pub async fn reply(&mut self, reply: &EditReply) -> Result<(), Box<dyn Error>> {
+        Ok(write_message(&mut self.writer, reply).await?)
+    }
+}
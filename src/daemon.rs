@@ -0,0 +1,497 @@
+// daemon.rs
+use chrono::{Local, NaiveDate};
+use diary_app::{CarryOverMode, Config, PromptMode, Storage};
+use std::error::Error;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::build_storage;
+use crate::bus::{BusEvent, BusService, SignallingStorage};
+use crate::carry_over::carried_tasks;
+use crate::config::ConfigManager;
+use crate::control::{ControlCommand, ControlRequest, EditReply, EditRequest, EditorClient};
+use crate::editor::{launch_editor, save_outcome, Cursor};
+use crate::habits::{habit_lines, insert_habits};
+use crate::iplocation::ipapi::get_ip_location;
+use crate::mood::MOOD_PROMPT;
+use crate::notification::{self, Answer};
+use crate::presence::{PresenceGate, StackedDetector, POLL_INTERVAL};
+use crate::scheduler::{record_action, Prompt, ReminderAction, Scheduler};
+use crate::template::{render_check_in, render_morning, TemplateContext};
+use crate::weather::open_weather::OpenWeatherService;
+
+/// Time left for the answer to `quit` to reach the client before the process exits.
+const QUIT_GRACE: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// An edit handed to the editor client, waiting for the user to finish.
+struct PendingEdit {
+    date: NaiveDate,
+    answer: oneshot::Receiver<EditReply>,
+}
+
+/// A notification asking whether to write now, waiting for the user.
+struct PendingQuestion {
+    answer: oneshot::Receiver<Answer>,
+}
+
+/// The long running side of the app: prompts on schedule and answers control commands.
+///
+/// Prompts go to the attached editor client when there is one, so the daemon itself can
+/// run without a display. Without a client it opens the editor itself, as long as it
+/// was started inside a desktop session. In notification mode a due prompt first asks
+/// with a desktop notification.
+pub struct Daemon {
+    config: Config,
+    storage: Box<dyn Storage>,
+    scheduler: Scheduler,
+    presence: PresenceGate,
+    reminder_log: PathBuf,
+    bus: Option<BusService>,
+    client: Option<EditorClient>,
+    editing: Option<PendingEdit>,
+    asking: Option<PendingQuestion>,
+}
+
+impl Daemon {
+    pub fn new(config: Config, bus: Option<BusService>) -> Result<Self, Box<dyn Error>> {
+        Ok(Daemon {
+            storage: open_storage(&config, bus.as_ref())?,
+            scheduler: Scheduler::new(&config),
+            presence: PresenceGate::new(Arc::new(StackedDetector::session()), config.idle.clone()),
+            reminder_log: ConfigManager::data_dir().join("reminders.log"),
+            config,
+            bus,
+            client: None,
+            editing: None,
+            asking: None,
+        })
+    }
+
+    /// Runs until a `quit` command comes in.
+    pub async fn run(
+        mut self,
+        mut control: mpsc::Receiver<ControlRequest>,
+        mut clients: mpsc::Receiver<EditorClient>,
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            let today = self.scheduler.logical_date();
+            let entry = match self.storage.get_entry(today).await {
+                Ok(entry) => entry,
+                Err(err) => {
+                    eprintln!("Couldn't read today's entry, trying again shortly: {}", err);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    continue;
+                }
+            };
+            let (due_at, prompt) = self.scheduler.next_prompt(entry.is_some());
+
+            // Nothing new is prompted while an editor is open or a notification is waiting
+            let now = self.scheduler.now();
+            let busy = self.editing.is_some() || self.asking.is_some();
+            if !busy && due_at <= now && self.presence.ready(now).await {
+                self.emit(BusEvent::ReminderDue(prompt, due_at));
+                let prompted = match self.config.prompt_mode {
+                    PromptMode::Editor => self.prompt(today, entry, prompt).await,
+                    PromptMode::Notification => self.ask(today, entry, prompt).await,
+                };
+                self.recover(prompted);
+                continue;
+            }
+            let wait = if due_at > now {
+                self.scheduler.nap_towards(due_at)
+            } else {
+                POLL_INTERVAL
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                Some(request) = control.recv() => {
+                    match self.handle(request, today, entry, due_at, prompt).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            tokio::time::sleep(QUIT_GRACE).await;
+                            return Ok(());
+                        }
+                        Err(err) => self.recover(Err(err)),
+                    }
+                }
+                Some(client) = clients.recv() => self.attach(client),
+                (date, answer) = wait_for_edit(&mut self.editing) => {
+                    self.editing = None;
+                    match answer {
+                        Ok(reply) => {
+                            let finished = self.finish_edit(date, reply).await;
+                            self.recover(finished);
+                        }
+                        Err(_) => {
+                            eprintln!("The editor client went away before the edit was done");
+                            self.scheduler.record_prompt();
+                        }
+                    }
+                }
+                answer = wait_for_answer(&mut self.asking) => {
+                    self.asking = None;
+                    let answered = match answer {
+                        Answer::Open => self.prompt(today, entry, prompt).await,
+                        Answer::Later(action) => self.apply(action),
+                        Answer::Dismissed => {
+                            self.scheduler.record_prompt();
+                            Ok(())
+                        }
+                    };
+                    self.recover(answered);
+                }
+            }
+        }
+    }
+
+    /// Acts on a control command, returns `false` when the daemon should stop.
+    async fn handle(
+        &mut self,
+        request: ControlRequest,
+        today: NaiveDate,
+        entry: Option<String>,
+        due_at: chrono::NaiveDateTime,
+        prompt: Prompt,
+    ) -> Result<bool, Box<dyn Error>> {
+        if request.is_abandoned() {
+            println!("Dropping {:?}, its sender gave up waiting", request.command);
+            return Ok(true);
+        }
+        match request.command.clone() {
+            ControlCommand::Open if self.editing.is_some() => {
+                request.fail("The editor is open already");
+            }
+            ControlCommand::Open => {
+                request.respond("Opening the editor");
+                // Whatever the notification gets answered with is moot now
+                self.asking = None;
+                let prompt = if entry.is_some() {
+                    Prompt::CheckIn
+                } else {
+                    Prompt::Morning
+                };
+                self.prompt(today, entry, prompt).await?;
+            }
+            ControlCommand::Snooze(duration) => {
+                self.apply(ReminderAction::Snooze(duration))?;
+                request.respond(format!("Snoozed for {} minutes", duration.num_minutes()));
+            }
+            ControlCommand::Skip => {
+                self.apply(ReminderAction::Skip)?;
+                request.respond("Skipped the next prompt");
+            }
+            ControlCommand::Reload => match self.reload() {
+                Ok(()) => request.respond("Configuration reloaded"),
+                Err(err) => request.fail(format!("Reload failed: {}", err)),
+            },
+            ControlCommand::Status => {
+                let mut status = format!(
+                    "Running as pid {}, diary date {}, next {} prompt at {}",
+                    std::process::id(),
+                    today,
+                    prompt,
+                    due_at.format("%Y-%m-%d %H:%M")
+                );
+                if self.editing.is_some() {
+                    status.push_str(", waiting for the editor client");
+                } else if self.asking.is_some() {
+                    status.push_str(", waiting for an answer to the notification");
+                } else if self.client.as_ref().is_some_and(|c| !c.is_closed()) {
+                    status.push_str(", editor client attached");
+                }
+                request.respond(status);
+            }
+            ControlCommand::Entry(date) => {
+                let date = date.unwrap_or(today);
+                match self.storage.get_entry(date).await {
+                    Ok(Some(content)) => request.respond(content),
+                    Ok(None) => request.fail(format!("No entry for {}", date)),
+                    Err(err) => request.fail(format!("Failed to read the entry: {}", err)),
+                }
+            }
+            // The client saves the whole entry, which would drop a note added meanwhile
+            ControlCommand::Append(_) if self.editing.is_some() => {
+                request.fail(
+                    "Today's entry is open in the editor client, add the note once it's saved",
+                );
+            }
+            ControlCommand::Append(note) => match self.append(today, &note).await {
+                Ok(()) => request.respond(format!("Note added to {}", today)),
+                Err(err) => request.fail(format!("Failed to save the note: {}", err)),
+            },
+            ControlCommand::Attach => {
+                request.fail("Only a control socket connection can attach");
+            }
+            ControlCommand::Quit => {
+                request.respond("Stopping");
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Keeps going after a prompt failed, e.g. when the storage is unreachable. The
+    /// prompt counts as shown, so it comes back at the next slot rather than straight away.
+    fn recover(&mut self, result: Result<(), Box<dyn Error>>) {
+        if let Err(err) = result {
+            eprintln!("The prompt failed, trying again at the next one: {}", err);
+            self.scheduler.record_prompt();
+        }
+    }
+
+    /// Takes `client` as the editor client, unless one is attached already.
+    fn attach(&mut self, client: EditorClient) {
+        if self.client.as_ref().is_some_and(|c| !c.is_closed()) {
+            println!("Refusing a second editor client");
+            client.refuse("Another editor client is already attached");
+            return;
+        }
+        println!("Editor client attached");
+        self.client = Some(client);
+    }
+
+    fn apply(&mut self, action: ReminderAction) -> Result<(), Box<dyn Error>> {
+        println!("Reminder {}", action);
+        self.scheduler.apply(action);
+        record_action(&self.reminder_log, self.scheduler.now(), action)
+    }
+
+    fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        let config = ConfigManager::load()?.ok_or("No configuration found")?;
+        self.storage = open_storage(&config, self.bus.as_ref())?;
+        self.scheduler.reconfigure(&config);
+        self.presence.reconfigure(config.idle.clone());
+        self.config = config;
+        Ok(())
+    }
+
+    fn emit(&self, event: BusEvent) {
+        if let Some(bus) = &self.bus {
+            bus.emit(event);
+        }
+    }
+
+    /// Adds `note` to the entry as a section of its own, the way a check-in would. The
+    /// entry is read again first, it may have been saved since the loop last looked.
+    async fn append(&self, date: NaiveDate, note: &str) -> Result<(), Box<dyn Error>> {
+        let entry = self.storage.get_entry(date).await?;
+        self.storage.save_entry(date, &with_note(entry, note)).await
+    }
+
+    /// The morning template for `today`, with a checkbox for every habit.
+    fn morning_entry(&self, today: NaiveDate, context: TemplateContext) -> String {
+        let content = render_morning(&self.config.templates, today, context);
+        insert_habits(&content, &self.config.habits.names)
+    }
+
+    /// Open tasks from the last entry before `today`, as `{{carried_tasks}}` lines.
+    async fn carried_over(&self, today: NaiveDate, template: &str) -> String {
+        if self.config.carry_over.mode == CarryOverMode::Off {
+            return String::new();
+        }
+        match self.storage.get_latest_entry().await {
+            Ok(Some((date, entry))) if date < today => {
+                carried_tasks(&self.config.carry_over, date, &entry, template)
+            }
+            Ok(_) => String::new(),
+            Err(err) => {
+                eprintln!(
+                    "Couldn't read the last entry to carry its tasks over: {}",
+                    err
+                );
+                String::new()
+            }
+        }
+    }
+
+    async fn prompt(
+        &mut self,
+        today: NaiveDate,
+        entry: Option<String>,
+        prompt: Prompt,
+    ) -> Result<(), Box<dyn Error>> {
+        let (content, cursor) = match prompt {
+            Prompt::Morning => {
+                let mut city = get_ip_location().await.unwrap_or_else(|err| {
+                    eprintln!("Couldn't look up the location: {}", err);
+                    String::new()
+                });
+                if city.is_empty() {
+                    city = self.config.city.clone();
+                }
+                let weather_service = match std::env::var("WEATHER_API_KEY") {
+                    Ok(api_key) => Some(OpenWeatherService::new(&city, &api_key)),
+                    Err(_) => None,
+                };
+
+                let weather = match &weather_service {
+                    Some(s) => s.get_weather().unwrap_or_else(|err| {
+                        eprintln!("Couldn't get the weather: {}", err);
+                        String::new()
+                    }),
+                    None => "API Not Configured".to_string(),
+                };
+                let context = TemplateContext {
+                    city,
+                    weather,
+                    habits: habit_lines(&self.config.habits.names),
+                    ..Default::default()
+                };
+                let mut content = self.morning_entry(today, context.clone());
+                // Rendered without them first, so tasks the template plans anyway aren't doubled
+                let carried = self.carried_over(today, &content).await;
+                if !carried.is_empty() {
+                    let context = TemplateContext {
+                        carried_tasks: carried,
+                        ..context
+                    };
+                    content = self.morning_entry(today, context);
+                }
+                (content, None)
+            }
+            Prompt::CheckIn => {
+                // Collect thoughts
+                let entry = entry.unwrap_or_default();
+                println!("Existing Content:`{}`", &entry);
+                let mut section = render_check_in(&self.config.templates, today);
+                if self.config.mood.prompt {
+                    section.push_str(MOOD_PROMPT);
+                }
+                let content = format!("{}\n\n{}", entry, section);
+                // Start typing under the new heading rather than at the top of the day
+                let cursor = Cursor::end_of(&content);
+                (content, Some(cursor))
+            }
+        };
+        self.edit(today, content, cursor).await
+    }
+
+    /// Asks with a notification whether to write now, opens the editor straight away
+    /// when no notification can be shown.
+    async fn ask(
+        &mut self,
+        today: NaiveDate,
+        entry: Option<String>,
+        prompt: Prompt,
+    ) -> Result<(), Box<dyn Error>> {
+        match notification::ask(prompt) {
+            Ok(answer) => {
+                self.asking = Some(PendingQuestion { answer });
+                Ok(())
+            }
+            Err(err) => {
+                eprintln!("Couldn't show a notification, opening the editor: {}", err);
+                self.prompt(today, entry, prompt).await
+            }
+        }
+    }
+
+    /// Gets `content` edited, by the editor client when one is attached.
+    async fn edit(
+        &mut self,
+        date: NaiveDate,
+        content: String,
+        cursor: Option<Cursor>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut content = content;
+        if let Some(client) = self.client.take() {
+            let request = EditRequest {
+                date,
+                content,
+                cursor,
+            };
+            match client.edit(request).await {
+                Ok(answer) => {
+                    self.client = Some(client);
+                    self.editing = Some(PendingEdit { date, answer });
+                    return Ok(());
+                }
+                Err(request) => content = request.content,
+            }
+        }
+
+        let in_terminal = self.config.editor.builtin && std::io::stdin().is_terminal();
+        if !in_desktop_session() && !in_terminal {
+            println!("No editor client attached, run `diary_app client` in your desktop session");
+            self.scheduler.record_prompt();
+            return Ok(());
+        }
+        let action = launch_editor(
+            self.storage.as_ref(),
+            &self.config.editor,
+            date,
+            content,
+            cursor,
+        )
+        .await?;
+        self.after_prompt(action)
+    }
+
+    async fn finish_edit(
+        &mut self,
+        date: NaiveDate,
+        reply: EditReply,
+    ) -> Result<(), Box<dyn Error>> {
+        save_outcome(self.storage.as_ref(), date, reply.outcome).await?;
+        self.after_prompt(reply.action)
+    }
+
+    fn after_prompt(&mut self, action: Option<ReminderAction>) -> Result<(), Box<dyn Error>> {
+        match action {
+            Some(action) => self.apply(action),
+            None => {
+                self.scheduler.record_prompt();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The configured storage, announcing saves on the bus when there is one.
+fn open_storage(
+    config: &Config,
+    bus: Option<&BusService>,
+) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+    let storage = build_storage(config)?;
+    Ok(match bus {
+        Some(bus) => Box::new(SignallingStorage::new(bus.clone(), storage)),
+        None => storage,
+    })
+}
+
+/// Resolves with the client's answer once the pending edit is done, never without one.
+async fn wait_for_edit(
+    editing: &mut Option<PendingEdit>,
+) -> (NaiveDate, Result<EditReply, oneshot::error::RecvError>) {
+    match editing {
+        Some(pending) => (pending.date, (&mut pending.answer).await),
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves with the answer to the pending notification, never without one.
+async fn wait_for_answer(asking: &mut Option<PendingQuestion>) -> Answer {
+    match asking {
+        Some(pending) => (&mut pending.answer).await.unwrap_or(Answer::Dismissed),
+        None => std::future::pending().await,
+    }
+}
+
+/// `entry` with `note` added as a section stamped with the current time.
+pub fn with_note(entry: Option<String>, note: &str) -> String {
+    let section = format!("## {}\n\n{}\n", Local::now().format("%H:%M"), note.trim());
+    match entry {
+        Some(entry) => format!("{}\n\n{}", entry.trim_end(), section),
+        None => section,
+    }
+}
+
+/// Whether an editor started from here would show up on the user's screen.
+fn in_desktop_session() -> bool {
+    cfg!(target_os = "windows")
+        || std::env::var_os("DISPLAY").is_some()
+        || std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
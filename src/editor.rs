@@ -0,0 +1,444 @@
+// editor.rs
+use chrono::{Duration, NaiveDate};
+use dialoguer::Confirm;
+use diary_app::{EditorConfig, Storage};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::Command;
+
+use crate::capture::capture;
+use crate::mood::drop_unanswered;
+use crate::scheduler::{ReminderAction, MAX_SNOOZE_MINUTES};
+
+/// An edit that keeps less than this share of what the editor was opened with needs a second look.
+const SHRINK_RATIO: f64 = 0.5;
+/// Short entries, like a fresh morning template, are allowed to shrink freely.
+const SHRINK_MIN_LEN: usize = 200;
+/// Minutes a bare `!snooze` marker puts the prompt off for.
+pub const DEFAULT_SNOOZE_MINUTES: i64 = 15;
+/// Runs the editor given as its arguments and writes its exit status to the file in
+/// `$0`. Terminal emulators don't pass it on, so a `:cq` would go unnoticed otherwise.
+const STATUS_WRAPPER: &str = r#""$@"; echo $? > "$0""#;
+/// Terminals tried in turn when none is configured, with the arguments that make each
+/// run the editor and wait for it to exit.
+const KNOWN_TERMINALS: &[&str] = &[
+    "x-terminal-emulator -e {editor}",
+    "gnome-terminal --wait -- {editor}",
+    "konsole -e {editor}",
+    "xfce4-terminal --disable-server -x {editor}",
+    "kitty {editor}",
+    "alacritty -e {editor}",
+    "foot {editor}",
+    "wezterm start -- {editor}",
+    "xterm -e {editor}",
+];
+
+/// Where the editor should put the cursor, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Cursor {
+    /// The end of `content`, where a check-in's new section waits to be written.
+    pub fn end_of(content: &str) -> Self {
+        let last_line = content.lines().last().unwrap_or_default();
+        Cursor {
+            line: content.lines().count().max(1),
+            column: last_line.chars().count() + 1,
+        }
+    }
+}
+
+/// What to do with the buffer once the editor has closed.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum EditOutcome {
+    Save(String),
+    Unchanged,
+    Aborted(String),
+}
+
+/// Opens `new_content` in the editor and saves the result as the entry for `date`.
+///
+/// A `!snooze [minutes]` or `!skip` line in the buffer is taken out and returned, so
+/// the prompt can be rescheduled.
+pub async fn launch_editor(
+    storage: &dyn Storage,
+    editor: &EditorConfig,
+    date: NaiveDate,
+    new_content: String,
+    cursor: Option<Cursor>,
+) -> Result<Option<ReminderAction>, Box<dyn Error>> {
+    let (outcome, action) = edit(editor, &new_content, cursor)?;
+    save_outcome(storage, date, outcome).await?;
+    Ok(action)
+}
+
+/// Opens `content` in the editor and works out what to do with the result.
+///
+/// The editor is started in the current session, so this has to run where the user
+/// can see it, which the background daemon might not.
+pub fn edit(
+    editor: &EditorConfig,
+    content: &str,
+    cursor: Option<Cursor>,
+) -> Result<(EditOutcome, Option<ReminderAction>), Box<dyn Error>> {
+    let edited = if use_capture_window(editor) {
+        capture(content, cursor)
+    } else {
+        edit_externally(editor, content, cursor)
+    };
+
+    Ok(match edited {
+        Ok(updated_content) => {
+            let (updated_content, action) = take_reminder_action(&updated_content);
+            // A mood prompt left blank is as good as no change
+            let updated_content = drop_unanswered(&updated_content);
+            let confirm = std::io::stdin()
+                .is_terminal()
+                .then_some(confirm_shrink as fn(&str) -> bool);
+            (
+                check_edit(&drop_unanswered(content), updated_content, confirm),
+                action,
+            )
+        }
+        Err(err) => (EditOutcome::Aborted(err.to_string()), None),
+    })
+}
+
+/// Whether to capture in the built-in window, which needs to be running in a terminal:
+/// when configured, or when there's no terminal emulator to start the editor in.
+fn use_capture_window(editor: &EditorConfig) -> bool {
+    let needs_terminal = !editor.gui && !cfg!(target_os = "windows") && editor.terminal.is_none();
+    std::io::stdin().is_terminal()
+        && (editor.builtin || (needs_terminal && detect_terminals().is_empty()))
+}
+
+/// Runs the external editor on a temporary copy of `content` and reads it back.
+fn edit_externally(
+    editor: &EditorConfig,
+    content: &str,
+    cursor: Option<Cursor>,
+) -> Result<String, Box<dyn Error>> {
+    let temp_file = tempfile::NamedTempFile::new()?;
+    let status_file = tempfile::NamedTempFile::new()?;
+    std::fs::write(temp_file.path(), content)?;
+    println!("Opening editor for recording regular response");
+    let status = editor_command(editor, temp_file.path(), cursor, status_file.path())
+        .and_then(|mut command| Ok(command.status()?))
+        .map_err(|err| format!("Failed to open the editor: {}", err))?;
+    if !status.success() {
+        return Err(format!("the editor exited with {}", status).into());
+    }
+    // Left empty when the editor ran without the wrapper
+    let editor_status = std::fs::read_to_string(status_file.path())?;
+    match editor_status.trim() {
+        "" | "0" => Ok(std::fs::read_to_string(temp_file.path())?),
+        code => Err(format!("the editor exited with status {}", code).into()),
+    }
+}
+
+/// The command that opens `file` in the configured editor, inside a terminal unless
+/// the editor brings its own window. The cursor is placed for editors known to take a
+/// position on the command line. In a terminal the editor's exit status is written
+/// to `status_file`.
+pub fn editor_command(
+    editor: &EditorConfig,
+    file: &Path,
+    cursor: Option<Cursor>,
+    status_file: &Path,
+) -> Result<Command, Box<dyn Error>> {
+    let mut editor_line = editor_words(editor);
+    editor_line.extend(editor.args.iter().cloned());
+    let file = file.to_string_lossy().into_owned();
+    let program = editor_line.first().map(|program| program_name(program));
+    match (program, cursor) {
+        (Some(program), Some(cursor)) => editor_line.extend(file_at(&program, file, cursor)),
+        _ => editor_line.push(file),
+    }
+
+    let words = if editor.gui || cfg!(target_os = "windows") {
+        editor_line
+    } else {
+        let template = editor
+            .terminal
+            .clone()
+            .or_else(|| detect_terminals().into_iter().next())
+            .ok_or("no terminal found to run the editor in, set `editor.terminal` in the config")?;
+        let mut wrapped = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            STATUS_WRAPPER.to_string(),
+            status_file.to_string_lossy().into_owned(),
+        ];
+        wrapped.extend(editor_line);
+        expand_terminal(&template, wrapped)
+    };
+
+    let (program, args) = words.split_first().ok_or("the editor command is empty")?;
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+/// The editor and its own arguments: the configured command, `$VISUAL`, `$EDITOR`, and
+/// vim or notepad as a last resort.
+fn editor_words(editor: &EditorConfig) -> Vec<String> {
+    let configured = editor
+        .command
+        .clone()
+        .or_else(|| std::env::var("VISUAL").ok())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|command| !command.trim().is_empty());
+    let command = configured.unwrap_or_else(|| {
+        if cfg!(target_os = "windows") {
+            "notepad".to_string()
+        } else {
+            "vim".to_string()
+        }
+    });
+    command.split_whitespace().map(str::to_string).collect()
+}
+
+/// `program` without its directory and extension, e.g. `nvim` for `/usr/bin/nvim`.
+fn program_name(program: &str) -> String {
+    Path::new(program)
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The arguments opening `file` with the cursor at `cursor`, in the way `program`
+/// understands, or just `file` for editors that don't take a position.
+fn file_at(program: &str, file: String, cursor: Cursor) -> Vec<String> {
+    let Cursor { line, column } = cursor;
+    match program {
+        // Straight into insert mode, the new section is there to be written
+        "vim" | "nvim" | "gvim" => {
+            vec![format!("+{}", line), "+startinsert!".to_string(), file]
+        }
+        "vi" => vec![format!("+{}", line), file],
+        "nano" => vec![format!("+{},{}", line, column), file],
+        "emacs" | "emacsclient" | "micro" | "kak" | "gedit" => {
+            vec![format!("+{}:{}", line, column), file]
+        }
+        "code" | "codium" | "code-insiders" => {
+            vec![
+                "--goto".to_string(),
+                format!("{}:{}:{}", file, line, column),
+            ]
+        }
+        "subl" | "zed" | "hx" | "helix" => vec![format!("{}:{}:{}", file, line, column)],
+        "kate" | "kwrite" => vec![
+            "--line".to_string(),
+            line.to_string(),
+            "--column".to_string(),
+            column.to_string(),
+            file,
+        ],
+        "notepad++" => vec![format!("-n{}", line), format!("-c{}", column), file],
+        _ => vec![file],
+    }
+}
+
+/// Fills the editor command line in for `{editor}`, or puts it at the end when the
+/// template doesn't say where.
+fn expand_terminal(template: &str, editor_line: Vec<String>) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut editor_line = Some(editor_line);
+    for word in template.split_whitespace() {
+        if word == "{editor}" {
+            words.extend(editor_line.take().unwrap_or_default());
+        } else {
+            words.push(word.to_string());
+        }
+    }
+    words.extend(editor_line.unwrap_or_default());
+    words
+}
+
+/// Terminal templates for the terminals installed here, `$TERMINAL` first.
+pub fn detect_terminals() -> Vec<String> {
+    let from_env = std::env::var("TERMINAL")
+        .ok()
+        .filter(|terminal| !terminal.trim().is_empty())
+        .map(|terminal| format!("{} -e {{editor}}", terminal));
+    let known = KNOWN_TERMINALS
+        .iter()
+        .filter(|template| {
+            let program = template.split_whitespace().next().unwrap_or_default();
+            in_path(program)
+        })
+        .map(|template| template.to_string());
+    from_env.into_iter().chain(known).collect()
+}
+
+fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Saves the entry if the edit was good, otherwise says why not.
+pub async fn save_outcome(
+    storage: &dyn Storage,
+    date: NaiveDate,
+    outcome: EditOutcome,
+) -> Result<(), Box<dyn Error>> {
+    match outcome {
+        EditOutcome::Save(updated_content) => storage.save_entry(date, &updated_content).await?,
+        EditOutcome::Unchanged => println!("Nothing was written, entry left as it was"),
+        EditOutcome::Aborted(reason) => eprintln!("Not saving the entry: {}", reason),
+    }
+    Ok(())
+}
+
+/// Strips `!snooze [minutes]` and `!skip` marker lines, returning the last one found.
+pub fn take_reminder_action(content: &str) -> (String, Option<ReminderAction>) {
+    let marker = Regex::new(r"^\s*!(snooze|skip)(?:\s+(\d+))?\s*$").unwrap();
+    let mut action = None;
+    let mut kept = Vec::new();
+    for line in content.split_inclusive('\n') {
+        match marker.captures(line.trim_end_matches(['\r', '\n'])) {
+            Some(captures) if &captures[1] == "skip" => action = Some(ReminderAction::Skip),
+            Some(captures) => {
+                // Too long a snooze is cut down to the longest there is
+                let minutes = match captures.get(2).map(|m| m.as_str().parse::<u64>()) {
+                    Some(Ok(0)) | None => DEFAULT_SNOOZE_MINUTES,
+                    Some(Ok(m)) => m.min(MAX_SNOOZE_MINUTES as u64) as i64,
+                    Some(Err(_)) => MAX_SNOOZE_MINUTES,
+                };
+                action = Some(ReminderAction::Snooze(Duration::minutes(minutes)));
+            }
+            None => kept.push(line),
+        }
+    }
+    (kept.concat(), action)
+}
+
+/// Decides whether an edit is safe to save.
+///
+/// An emptied buffer is never saved. A large shrink is put to `confirm` when there's
+/// a way to ask, otherwise it's saved with a warning since history still has the old
+/// version.
+pub fn check_edit(
+    original: &str,
+    updated: String,
+    confirm: Option<fn(&str) -> bool>,
+) -> EditOutcome {
+    if updated == original {
+        return EditOutcome::Unchanged;
+    }
+    if updated.trim().is_empty() {
+        return EditOutcome::Aborted("the edited entry is empty".to_string());
+    }
+
+    let original_len = original.trim().chars().count();
+    let updated_len = updated.trim().chars().count();
+    if original_len >= SHRINK_MIN_LEN && (updated_len as f64) < original_len as f64 * SHRINK_RATIO {
+        let message = format!(
+            "The entry shrank from {} to {} characters",
+            original_len, updated_len
+        );
+        if let Some(confirm) = confirm {
+            if !confirm(&message) {
+                return EditOutcome::Aborted(message);
+            }
+        } else {
+            eprintln!(
+                "{}, saving it. The previous version is kept, see `diary_app history`",
+                message
+            );
+        }
+    }
+    EditOutcome::Save(updated)
+}
+
+/// Asks on the terminal whether to save a shrunk entry anyway.
+fn confirm_shrink(message: &str) -> bool {
+    Confirm::new()
+        .with_prompt(format!("{}, save it anyway?", message))
+        .default(false)
+        .interact()
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_out_reminder_markers() {
+        let (content, action) = take_reminder_action("Busy day\n!snooze 30\nmore\n");
+        assert_eq!(content, "Busy day\nmore\n");
+        assert_eq!(action, Some(ReminderAction::Snooze(Duration::minutes(30))));
+
+        let (content, action) = take_reminder_action("  !snooze\n!skip\n");
+        assert_eq!(content, "");
+        assert_eq!(action, Some(ReminderAction::Skip));
+
+        let (_, action) = take_reminder_action("!snooze 0\n");
+        assert_eq!(
+            action,
+            Some(ReminderAction::Snooze(Duration::minutes(
+                DEFAULT_SNOOZE_MINUTES
+            )))
+        );
+        let (_, action) = take_reminder_action("!snooze 99999999999999999999999\n");
+        assert_eq!(
+            action,
+            Some(ReminderAction::Snooze(Duration::minutes(
+                MAX_SNOOZE_MINUTES
+            )))
+        );
+        assert_eq!(
+            take_reminder_action("not !skip\n"),
+            ("not !skip\n".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn checks_edits_before_saving() {
+        assert_eq!(
+            check_edit("same", "same".to_string(), None),
+            EditOutcome::Unchanged
+        );
+        assert_eq!(
+            check_edit("something", " \n".to_string(), None),
+            EditOutcome::Aborted("the edited entry is empty".to_string())
+        );
+        assert_eq!(
+            check_edit("short", "s".to_string(), None),
+            EditOutcome::Save("s".to_string())
+        );
+    }
+
+    #[test]
+    fn confirms_a_big_shrink_when_it_can_ask() {
+        let original = "x".repeat(SHRINK_MIN_LEN * 2);
+        let shrunk = "x".repeat((original.len() as f64 * SHRINK_RATIO) as usize - 1);
+        let message = format!(
+            "The entry shrank from {} to {} characters",
+            original.len(),
+            shrunk.len()
+        );
+
+        // Without a way to ask it's saved
+        assert_eq!(
+            check_edit(&original, shrunk.clone(), None),
+            EditOutcome::Save(shrunk.clone())
+        );
+        assert_eq!(
+            check_edit(&original, shrunk.clone(), Some(|_| true)),
+            EditOutcome::Save(shrunk.clone())
+        );
+        assert_eq!(
+            check_edit(&original, shrunk, Some(|_| false)),
+            EditOutcome::Aborted(message)
+        );
+    }
+}
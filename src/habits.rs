@@ -0,0 +1,147 @@
+// habits.rs
+use chrono::NaiveDate;
+
+use crate::task_manager::parse_tasks;
+
+/// Where habits the morning template has no checkbox for are added.
+const HABITS_HEADING: &str = "# Habits";
+
+/// How a habit went over a range of days.
+#[derive(Debug, PartialEq)]
+pub struct HabitStats {
+    /// Days in a row it was done up to the end of the range, which may still be open
+    /// when it's today
+    pub current_streak: usize,
+    pub best_streak: usize,
+    pub done: usize,
+    /// Days with a checkbox for it
+    pub tracked: usize,
+}
+
+/// A `- [ ]` line per habit, for the `{{habits}}` placeholder.
+pub fn habit_lines(habits: &[String]) -> String {
+    habits
+        .iter()
+        .map(|habit| format!("- [ ] {}", habit))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Adds a checkbox for each habit `entry` has none for, e.g. when the morning template
+/// leaves out `{{habits}}`.
+pub fn insert_habits(entry: &str, habits: &[String]) -> String {
+    let missing: Vec<String> = habits
+        .iter()
+        .filter(|habit| checked(entry, habit).is_none())
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        return entry.to_string();
+    }
+    format!(
+        "{}\n\n{}\n{}\n",
+        entry.trim_end(),
+        HABITS_HEADING,
+        habit_lines(&missing)
+    )
+}
+
+/// Whether the habit's box is ticked in `entry`, `None` when it has none.
+pub fn checked(entry: &str, habit: &str) -> Option<bool> {
+    // Only the checkboxes matter here, not which day they're from
+    parse_tasks(NaiveDate::default(), entry)
+        .into_iter()
+        .find(|task| task.text.eq_ignore_ascii_case(habit.trim()))
+        .map(|task| task.done)
+}
+
+/// Streaks and completion from `days`, a day each oldest first, `None` for days
+/// without an entry or a checkbox for the habit. `ends_today` when the last day is
+/// today.
+pub fn habit_stats(days: &[Option<bool>], ends_today: bool) -> HabitStats {
+    let mut stats = HabitStats {
+        current_streak: 0,
+        best_streak: 0,
+        done: 0,
+        tracked: 0,
+    };
+    let mut streak = 0;
+    for day in days {
+        streak = if *day == Some(true) { streak + 1 } else { 0 };
+        stats.best_streak = stats.best_streak.max(streak);
+        stats.tracked += day.is_some() as usize;
+        stats.done += (*day == Some(true)) as usize;
+    }
+    // Today isn't over yet, so it doesn't break the streak until it's done
+    let open_today = (ends_today && days.last().is_some_and(|day| *day != Some(true))) as usize;
+    stats.current_streak = days
+        .iter()
+        .rev()
+        .skip(open_today)
+        .take_while(|day| **day == Some(true))
+        .count();
+    stats
+}
+
+/// A square a day, filled when the habit was done, grouped by week.
+pub fn heatmap(days: &[Option<bool>]) -> String {
+    days.chunks(7)
+        .map(|week| {
+            week.iter()
+                .map(|day| match day {
+                    Some(true) => '■',
+                    Some(false) => '□',
+                    None => '·',
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_streaks() {
+        let days = [
+            Some(true),
+            Some(true),
+            Some(true),
+            None,
+            Some(true),
+            Some(false),
+            Some(true),
+            Some(true),
+            Some(false),
+        ];
+        assert_eq!(
+            habit_stats(&days, true),
+            HabitStats {
+                current_streak: 2,
+                best_streak: 3,
+                done: 6,
+                tracked: 8,
+            }
+        );
+        assert_eq!(habit_stats(&days[..5], true).current_streak, 1);
+        assert_eq!(habit_stats(&days[..6], true).current_streak, 1);
+        // A range ending before today has no open day
+        assert_eq!(habit_stats(&days, false).current_streak, 0);
+        assert_eq!(habit_stats(&days[..6], false).current_streak, 0);
+        assert_eq!(habit_stats(&days[..5], false).current_streak, 1);
+    }
+
+    #[test]
+    fn adds_missing_habits() {
+        let habits = vec!["Workout".to_string(), "Read".to_string()];
+        let entry = "# Tasks for today\n- [x] workout\n";
+        assert_eq!(checked(entry, "Workout"), Some(true));
+        assert_eq!(checked(entry, "Read"), None);
+        assert_eq!(
+            insert_habits(entry, &habits),
+            "# Tasks for today\n- [x] workout\n\n# Habits\n- [ ] Read\n"
+        );
+    }
+}
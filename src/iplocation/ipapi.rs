@@ -5,13 +5,13 @@ use reqwest::header::{HeaderMap, USER_AGENT};
 use serde_json::Value;
 
 pub async fn get_ip_location() -> Result<String, Box<dyn std::error::Error>> {
-    let url = format!("https://ipapi.co/json/");
+    let url = "https://ipapi.co/json/";
     let custom_user_agent = "User-Agent: Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36";
     let mut headers = HeaderMap::new();
     headers.insert(USER_AGENT, custom_user_agent.parse().unwrap());
     let client = reqwest::Client::new();
     let response: Value = client
-        .get(&url)
+        .get(url)
         .headers(headers)
         .send()
         .await
@@ -23,13 +23,13 @@ pub async fn get_ip_location() -> Result<String, Box<dyn std::error::Error>> {
     let region = response["region"].as_str().unwrap_or("");
     let country = response["country"].as_str().unwrap_or("");
 
-    let mut current_location = if city.len() > 0 && (region.len() > 0 || country.len() > 0) {
+    let mut current_location = if !city.is_empty() && (!region.is_empty() || !country.is_empty()) {
         format!("{},", city)
     } else {
         city.to_string()
     };
 
-    if region.len() > 0 && country.len() > 0 {
+    if !region.is_empty() && !country.is_empty() {
         current_location = format!("{}{},", current_location, region);
     }
     current_location = format!("{}{}", current_location, country);
@@ -1,20 +1,291 @@
 // lib.rs
 use async_trait::async_trait;
-use chrono::{NaiveDate, NaiveTime};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
 
+/// Line prefixes the morning entry uses for its header fields.
+pub const CITY_PREFIX: &str = "🌆 City:";
+pub const WEATHER_PREFIX: &str = "🌤️ Weather:";
+/// A `mood: 4 energy: 2` line, either field may be left out or blank.
+pub const MOOD_LINE: &str = r"(?i)^\s*(?:mood:[ \t]*(\d*))?[ \t]*(?:energy:[ \t]*(\d*))?\s*$";
+/// The heading a check-in starts its section with.
+const SECTION_HEADING: &str = r"^##\s+(\d{1,2}:\d{2})\s*$";
+/// `#tags` in the body or a task, numbers alone like `#1` aren't tags.
+const TAG: &str = r"(?:^|\s)#([\p{L}\p{N}_/-]*[\p{L}_/-][\p{L}\p{N}_/-]*)";
+
 #[async_trait(?Send)]
 pub trait Storage {
     async fn save_entry(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>>;
     async fn get_entry(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>>;
     async fn get_latest_entry(&self) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>>;
+    /// Dates that have an entry, oldest first.
+    async fn list_entries(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>>;
+
+    /// The entry for `date` with its metadata. Storages that keep metadata next to the
+    /// body return what they stored, the rest work it out from the body.
+    async fn get_entry_with_metadata(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<Entry>, Box<dyn Error>> {
+        Ok(self
+            .get_entry(date)
+            .await?
+            .map(|body| Entry::new(date, body)))
+    }
+
+    /// Saves the body and its metadata as given. Storages with no place for metadata
+    /// keep the body only.
+    async fn save_entry_with_metadata(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        self.save_entry(entry.date, &entry.body).await
+    }
+}
+
+/// A day in the diary: the Markdown body and what's known about it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub date: NaiveDate,
+    pub body: String,
+    pub metadata: EntryMetadata,
+}
+
+impl Entry {
+    /// An entry with the metadata its body gives away.
+    pub fn new(date: NaiveDate, body: String) -> Self {
+        let metadata = EntryMetadata::from_body(&body);
+        Entry {
+            date,
+            body,
+            metadata,
+        }
+    }
+
+    /// The body split at its check-in headings, see [`parse_sections`].
+    pub fn sections(&self) -> Vec<Section> {
+        parse_sections(self.date, &self.body)
+    }
+}
+
+/// The part of an entry written at one check-in, under its `## HH:MM` heading.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// When the check-in was, `None` for the text above the first heading
+    pub start: Option<NaiveDateTime>,
+    /// Time since the check-in before it
+    pub since_previous: Option<Duration>,
+    /// The text under the heading, trimmed
+    pub body: String,
+}
+
+/// Splits the entry for `date` at its check-in headings, in order. A check-in earlier
+/// than the one before it was written after midnight, so it's on the next day.
+pub fn parse_sections(date: NaiveDate, body: &str) -> Vec<Section> {
+    let heading = Regex::new(SECTION_HEADING).unwrap();
+    let mut sections = vec![Section {
+        start: None,
+        since_previous: None,
+        body: String::new(),
+    }];
+    let mut previous: Option<NaiveDateTime> = None;
+    for line in body.lines() {
+        let time = heading
+            .captures(line.trim_end())
+            .and_then(|captures| NaiveTime::parse_from_str(&captures[1], "%H:%M").ok());
+        let Some(time) = time else {
+            let body = &mut sections.last_mut().unwrap().body;
+            body.push_str(line);
+            body.push('\n');
+            continue;
+        };
+        let mut start = date.and_time(time);
+        if let Some(previous) = previous {
+            while start < previous {
+                start += Duration::days(1);
+            }
+        }
+        sections.push(Section {
+            start: Some(start),
+            since_previous: previous.map(|previous| start - previous),
+            body: String::new(),
+        });
+        previous = Some(start);
+    }
+    sections
+        .into_iter()
+        .map(|section| Section {
+            body: section.body.trim().to_string(),
+            ..section
+        })
+        .filter(|section| section.start.is_some() || !section.body.is_empty())
+        .collect()
+}
+
+/// Typed fields kept next to an entry's body, as YAML frontmatter or database properties.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct EntryMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weather: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The day's average on a 1 to 5 scale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mood: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub energy: Option<f64>,
+    /// When each check-in section was started
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<NaiveTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Local>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<DateTime<Local>>,
+}
+
+impl EntryMetadata {
+    pub fn from_body(body: &str) -> Self {
+        let mut metadata = EntryMetadata::default();
+        metadata.update_from_body("", body);
+        metadata
+    }
+
+    /// Brings the fields the body holds up to date after it changed from `previous`.
+    /// Location and weather are kept when the body doesn't mention them. Tags are the
+    /// body's, plus any added as metadata rather than in `previous`.
+    pub fn update_from_body(&mut self, previous: &str, body: &str) {
+        let field = |prefix: &str| {
+            body.lines()
+                .find_map(|line| {
+                    line.trim()
+                        .strip_prefix(prefix)
+                        .map(|v| v.trim().to_string())
+                })
+                // Left blank when the lookup failed
+                .filter(|v| !v.is_empty())
+        };
+        if let Some(location) = field(CITY_PREFIX) {
+            self.location = Some(location);
+        }
+        if let Some(weather) = field(WEATHER_PREFIX) {
+            self.weather = Some(weather);
+        }
+
+        let previous_tags = body_tags(previous);
+        let mut tags: Vec<String> = self
+            .tags
+            .drain(..)
+            .filter(|tag| !previous_tags.contains(tag))
+            .collect();
+        for tag in body_tags(body) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        self.tags = tags;
+
+        let mood = day_mood(body);
+        self.mood = mood.mood;
+        self.energy = mood.energy;
+
+        // Only the times are kept, not which day they're on
+        self.sections = parse_sections(NaiveDate::default(), body)
+            .iter()
+            .filter_map(|section| section.start.map(|start| start.time()))
+            .collect();
+    }
+}
+
+/// The `#tags` in `body` without the `#`, each once, in order.
+pub fn body_tags(body: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    for captures in Regex::new(TAG).unwrap().captures_iter(body) {
+        let tag = captures[1].to_string();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// A `mood: 4 energy: 2` line in an entry, values from 1 to 5. Either may be left out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoodReading {
+    /// The check-in it was written under
+    pub time: Option<NaiveTime>,
+    pub mood: Option<u8>,
+    pub energy: Option<u8>,
+}
+
+/// The averages of a day's readings.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DayMood {
+    pub mood: Option<f64>,
+    pub energy: Option<f64>,
+}
+
+/// The mood and energy lines in `body`, in order. Values outside 1 to 5 are ignored.
+pub fn parse_mood_readings(body: &str) -> Vec<MoodReading> {
+    let heading = Regex::new(SECTION_HEADING).unwrap();
+    let mood_line = Regex::new(MOOD_LINE).unwrap();
+    let value = |captures: &regex::Captures, group| {
+        captures
+            .get(group)
+            .and_then(|value| value.as_str().parse().ok())
+            .filter(|value| (1..=5).contains(value))
+    };
+
+    let mut time = None;
+    let mut readings = Vec::new();
+    for line in body.lines() {
+        if let Some(captures) = heading.captures(line.trim_end()) {
+            time = NaiveTime::parse_from_str(&captures[1], "%H:%M").ok();
+            continue;
+        }
+        let Some(captures) = mood_line.captures(line) else {
+            continue;
+        };
+        let reading = MoodReading {
+            time,
+            mood: value(&captures, 1),
+            energy: value(&captures, 2),
+        };
+        if reading.mood.is_some() || reading.energy.is_some() {
+            readings.push(reading);
+        }
+    }
+    readings
+}
+
+/// The day's average mood and energy, to one decimal.
+pub fn day_mood(body: &str) -> DayMood {
+    let readings = parse_mood_readings(body);
+    let average = |values: Vec<u8>| {
+        (!values.is_empty()).then(|| {
+            let sum: f64 = values.iter().map(|value| *value as f64).sum();
+            (sum / values.len() as f64 * 10.0).round() / 10.0
+        })
+    };
+    DayMood {
+        mood: average(readings.iter().filter_map(|reading| reading.mood).collect()),
+        energy: average(
+            readings
+                .iter()
+                .filter_map(|reading| reading.energy)
+                .collect(),
+        ),
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum StorageType {
     Local,
     Notion,
+    Vault,
+    Sqlite,
    // GoogleDrive,
 }
 /*
@@ -40,6 +311,168 @@ impl Config {
 }
 */
 
+/// How a due prompt reaches the user.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum PromptMode {
+    /// Open the editor straight away
+    #[default]
+    Editor,
+    /// Ask with a desktop notification first, offering Open, Snooze and Skip
+    Notification,
+}
+
+/// Which editor opens an entry, and how.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct EditorConfig {
+    /// Editor command line, `$VISUAL` or `$EDITOR` when unset, then vim (notepad on Windows)
+    pub command: Option<String>,
+    /// Extra arguments, put before the file name
+    pub args: Vec<String>,
+    /// The editor opens a window of its own and waits for it to close, e.g. `code --wait`
+    /// or `gvim -f`, so no terminal is started for it
+    pub gui: bool,
+    /// Terminal to run the editor in, `{editor}` stands for the editor command line, e.g.
+    /// `kitty -e {editor}`. An installed terminal is picked when unset
+    pub terminal: Option<String>,
+    /// Write in the built-in capture window in the current terminal instead
+    pub builtin: bool,
+}
+
+/// Template files for new entries, in the `templates` folder of the config dir.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TemplateConfig {
+    /// The morning entry
+    pub morning: String,
+    /// Morning templates for particular days, e.g. `{"Sat": "weekend.md"}`
+    pub weekdays: HashMap<Weekday, String>,
+    /// The section each check-in adds to the entry
+    pub check_in: String,
+}
+
+impl Default for TemplateConfig {
+    fn default() -> Self {
+        TemplateConfig {
+            morning: "morning.md".to_string(),
+            weekdays: HashMap::new(),
+            check_in: "check-in.md".to_string(),
+        }
+    }
+}
+
+/// Things to do every day, given a checkbox in each morning entry and reported on by
+/// `diary_app habits`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HabitConfig {
+    pub names: Vec<String>,
+}
+
+impl Default for HabitConfig {
+    fn default() -> Self {
+        HabitConfig {
+            names: vec![
+                "Eat Healthy".to_string(),
+                "Workout".to_string(),
+                "Talk to someone".to_string(),
+            ],
+        }
+    }
+}
+
+/// Mood and energy tracking, from `mood: 4 energy: 2` lines in the entries.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct MoodConfig {
+    /// Start each check-in with a `mood:  energy:` line to fill in, left out when blank
+    pub prompt: bool,
+}
+
+/// Which unfinished tasks the morning entry picks up from the day before.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum CarryOverMode {
+    Off,
+    /// Every unchecked `- [ ]` task
+    #[default]
+    All,
+    /// Only tasks marked with the carry-over tag
+    Tagged,
+}
+
+/// Carrying open tasks over from the previous entry into the next morning's.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CarryOverConfig {
+    pub mode: CarryOverMode,
+    /// Marks the tasks to carry in `Tagged` mode, e.g. `- [ ] Call the bank #carry`
+    pub tag: String,
+}
+
+impl Default for CarryOverConfig {
+    fn default() -> Self {
+        CarryOverConfig {
+            mode: CarryOverMode::default(),
+            tag: "#carry".to_string(),
+        }
+    }
+}
+
+/// How many overwritten versions of an entry are kept around for `restore`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub max_revisions: usize,
+    pub max_age_days: u32,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            max_revisions: 20,
+            max_age_days: 30,
+        }
+    }
+}
+
+/// When reminders may pop up. Empty lists and unset hours mean no restriction.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// Reminders only between these times, the range may wrap past midnight
+    pub active_from: Option<NaiveTime>,
+    pub active_until: Option<NaiveTime>,
+    /// Days reminders are allowed on, e.g. `["Mon", "Tue", "Wed", "Thu", "Fri"]`
+    pub days: Vec<Weekday>,
+    pub skip_dates: Vec<NaiveDate>,
+    /// Check in at these times of day instead of every `editor_frequency_minutes`
+    pub fixed_times: Vec<NaiveTime>,
+    /// Cron expressions for check-ins, `min hour day month weekday` or with seconds first.
+    /// Name weekdays, e.g. `0 9-17/2 * * Mon-Fri`, numbers count from Sunday = 1
+    pub cron: Vec<String>,
+}
+
+/// Holding prompts back while the user is typing or away from an unlocked screen.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct IdleConfig {
+    pub enabled: bool,
+    /// Seconds without input that count as a pause to prompt in
+    pub pause_seconds: u32,
+    /// Prompt anyway after waiting this long for a pause, the lock screen always waits
+    pub max_defer_minutes: u32,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        IdleConfig {
+            enabled: true,
+            pause_seconds: 20,
+            max_defer_minutes: 15,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
     pub storage_type: StorageType,
@@ -47,8 +480,40 @@ pub struct Config {
     pub notion_token: Option<String>,
     pub notion_database_id: Option<String>,
     pub google_drive_token: Option<String>,
+    pub vault_path: Option<String>,
+    /// Keep a local SQLite cache and search index next to the chosen storage
+    #[serde(default = "default_true")]
+    pub sqlite_index: bool,
     pub day_start_time: NaiveTime,
     pub editor_frequency_minutes: u32,
+    #[serde(default)]
+    pub prompt_mode: PromptMode,
+    #[serde(default)]
+    pub editor: EditorConfig,
+    #[serde(default)]
+    pub templates: TemplateConfig,
+    #[serde(default)]
+    pub carry_over: CarryOverConfig,
+    #[serde(default)]
+    pub habits: HabitConfig,
+    #[serde(default)]
+    pub mood: MoodConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    #[serde(default)]
+    pub idle: IdleConfig,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Config {
@@ -59,8 +524,107 @@ impl Config {
             notion_token: None,
             notion_database_id: None,
             google_drive_token: None,
+            vault_path: None,
+            sqlite_index: true,
             day_start_time: NaiveTime::from_hms_opt(5, 30, 0).unwrap(),
             editor_frequency_minutes: 60,
+            prompt_mode: PromptMode::default(),
+            editor: EditorConfig::default(),
+            templates: TemplateConfig::default(),
+            carry_over: CarryOverConfig::default(),
+            habits: HabitConfig::default(),
+            mood: MoodConfig::default(),
+            history: HistoryConfig::default(),
+            schedule: ScheduleConfig::default(),
+            idle: IdleConfig::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_left_out_settings() {
+        let mut settings = serde_json::to_value(Config::new()).unwrap();
+        let settings_map = settings.as_object_mut().unwrap();
+        settings_map.remove("sqlite_index");
+        settings_map.insert(
+            "history".to_string(),
+            serde_json::json!({ "max_revisions": 5 }),
+        );
+        let config: Config = serde_json::from_value(settings).unwrap();
+        assert!(config.sqlite_index);
+        assert_eq!(config.history.max_revisions, 5);
+        assert_eq!(
+            config.history.max_age_days,
+            HistoryConfig::default().max_age_days
+        );
+    }
+
+    #[test]
+    fn reads_readings_under_their_check_in() {
+        let entry = "Morning\nmood: 4 energy: 2\n## 15:00\n\nTired.\nEnergy: 1\nmood: 9\n";
+        let readings = parse_mood_readings(entry);
+        assert_eq!(readings.len(), 2);
+        assert_eq!(readings[0].time, None);
+        assert_eq!((readings[0].mood, readings[0].energy), (Some(4), Some(2)));
+        assert_eq!(readings[1].time, NaiveTime::from_hms_opt(15, 0, 0));
+        assert_eq!((readings[1].mood, readings[1].energy), (None, Some(1)));
+        assert_eq!(
+            day_mood(entry),
+            DayMood {
+                mood: Some(4.0),
+                energy: Some(1.5),
+            }
+        );
+    }
+
+    #[test]
+    fn works_metadata_out_of_the_body() {
+        let body = "🌆 City: Pune, MH\n🌤️ Weather: Clear, 24°C\n# Tasks for today\n- [ ] Call the bank #errand\n\n## 09:30\n\nIssue #12, #work/deploy\nmood: 3\n";
+        let entry = Entry::new(
+            NaiveDate::from_ymd_opt(2024, 3, 5).unwrap(),
+            body.to_string(),
+        );
+        assert_eq!(entry.metadata.location.as_deref(), Some("Pune, MH"));
+        assert_eq!(entry.metadata.weather.as_deref(), Some("Clear, 24°C"));
+        assert_eq!(entry.metadata.tags, vec!["errand", "work/deploy"]);
+        assert_eq!(entry.metadata.mood, Some(3.0));
+        assert_eq!(entry.metadata.energy, None);
+        assert_eq!(
+            entry.metadata.sections,
+            vec![NaiveTime::from_hms_opt(9, 30, 0).unwrap()]
+        );
+
+        let mut metadata = entry.metadata.clone();
+        metadata.tags.push("travel".to_string());
+        metadata.update_from_body(body, "Just a note #later");
+        assert_eq!(metadata.location.as_deref(), Some("Pune, MH"));
+        // Tags taken out of the body go, the one added as metadata stays
+        assert_eq!(metadata.tags, vec!["travel", "later"]);
+        assert_eq!(metadata.mood, None);
+        assert!(metadata.sections.is_empty());
+    }
+
+    #[test]
+    fn splits_sections_at_check_ins() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        let body = "🌆 City: Pune\n\n## 15:00\n\nThinking.\n\n## 16:30\n\n## 00:15\nLate.\n";
+        let sections = parse_sections(date, body);
+        assert_eq!(sections.len(), 4);
+        assert_eq!(sections[0].start, None);
+        assert_eq!(sections[0].body, "🌆 City: Pune");
+        assert_eq!(sections[1].start, date.and_hms_opt(15, 0, 0));
+        assert_eq!(sections[1].since_previous, None);
+        assert_eq!(sections[1].body, "Thinking.");
+        assert_eq!(sections[2].since_previous, Some(Duration::minutes(90)));
+        assert_eq!(sections[2].body, "");
+        assert_eq!(
+            sections[3].start,
+            date.succ_opt().unwrap().and_hms_opt(0, 15, 0)
+        );
+        assert_eq!(sections[3].since_previous, Some(Duration::minutes(465)));
+    }
+}
@@ -1,27 +1,50 @@
 // main.rs
+mod bus;
+mod capture;
+mod carry_over;
+mod cli;
+mod client;
 mod config;
+mod control;
+mod daemon;
+mod editor;
+mod habits;
+mod iplocation;
+mod mood;
+mod notification;
+mod presence;
+mod scheduler;
+mod search;
 mod setup;
 mod storage;
+mod task_manager;
+mod template;
 mod weather;
-mod iplocation;
 
-use chrono::Local;
+use bus::BusService;
+use chrono::{Duration, NaiveDate, NaiveTime};
 use clap::{App, Arg};
+use cli::{ExportFormat, SectionFilter, StatsMetric};
 use config::ConfigManager;
+use control::{ControlCommand, InstanceLock};
+use daemon::Daemon;
 // use daemonize::Daemonize;
-use dirs;
-use iplocation::ipapi::get_ip_location;
+use scheduler::MAX_SNOOZE_MINUTES;
+use search::{run_search, SearchOptions};
 use setup::{add_auto_start_entry, SetupWizard};
+use tokio::sync::mpsc;
 
-use storage::{local::LocalStorage, notion::NotionStorage};
-use weather::open_weather::OpenWeatherService;
-
-use diary_app::{Storage, StorageType};
+use diary_app::{Config, Storage, StorageType};
+use storage::{
+    history::{History, HistoryStorage},
+    local::LocalStorage,
+    notion::NotionStorage,
+    sqlite::SqliteStorage,
+    vault::VaultStorage,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("The |\\|/\\R\\| /\\PP");
-
     let matches = App::new("The Journal")
         .version("1.0")
         .author("Ravinder Payal")
@@ -29,24 +52,236 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .arg(Arg::with_name("config")
              .long("config")
              .help("Run configuration setup"))
+        .subcommand(App::new("daemon")
+             .about("Run in the background and prompt for entries, the default without a subcommand"))
+        .subcommand(App::new("show")
+             .about("Print an entry")
+             .arg(Arg::with_name("date")
+                  .help("Entry date (YYYY-MM-DD), today when left out"))
+             .arg(Arg::with_name("section")
+                  .long("section")
+                  .takes_value(true)
+                  .conflicts_with("since")
+                  .help("Only the check-in that was being written at this time (HH:MM)"))
+             .arg(Arg::with_name("since")
+                  .long("since")
+                  .takes_value(true)
+                  .help("Only the check-ins from the last while, e.g. 2h, 45m or 1h30m"))
+             .arg(Arg::with_name("metadata")
+                  .long("metadata")
+                  .help("Print the entry's metadata as YAML frontmatter above it")))
+        .subcommand(App::new("list")
+             .about("List the entries, with their length and first line")
+             .arg(Arg::with_name("from")
+                  .long("from")
+                  .takes_value(true)
+                  .help("Only entries on or after this date (YYYY-MM-DD)"))
+             .arg(Arg::with_name("to")
+                  .long("to")
+                  .takes_value(true)
+                  .help("Only entries on or before this date (YYYY-MM-DD)")))
+        .subcommand(App::new("export")
+             .about("Write entries out as JSON or Markdown files")
+             .arg(Arg::with_name("output")
+                  .help("File to write, or folder for --format markdown. JSON goes to stdout when left out"))
+             .arg(Arg::with_name("format")
+                  .long("format")
+                  .takes_value(true)
+                  .possible_values(["json", "markdown"])
+                  .default_value("json")
+                  .help("JSON array of entries, or a YYYY-MM-DD.md file per entry"))
+             .arg(Arg::with_name("from")
+                  .long("from")
+                  .takes_value(true)
+                  .help("Only entries on or after this date (YYYY-MM-DD)"))
+             .arg(Arg::with_name("to")
+                  .long("to")
+                  .takes_value(true)
+                  .help("Only entries on or before this date (YYYY-MM-DD)")))
+        .subcommand(App::new("import")
+             .about("Save entries from an export file or a folder of YYYY-MM-DD.md files")
+             .arg(Arg::with_name("input")
+                  .required(true)
+                  .help("JSON file written by `export`, or a folder of Markdown files"))
+             .arg(Arg::with_name("overwrite")
+                  .long("overwrite")
+                  .help("Replace entries that already exist, they stay in the history")))
+        .subcommand(App::new("tasks")
+             .about("List, add and tick off the tasks in your entries")
+             .subcommand_required(true)
+             .arg_required_else_help(true)
+             .subcommand(App::new("list")
+                  .about("List the open tasks, a task carried from day to day shows once")
+                  .arg(Arg::with_name("all")
+                       .long("all")
+                       .help("Include the tasks that are done"))
+                  .arg(Arg::with_name("tag")
+                       .long("tag")
+                       .takes_value(true)
+                       .help("Only tasks with this tag, e.g. errand"))
+                  .arg(Arg::with_name("from")
+                       .long("from")
+                       .takes_value(true)
+                       .help("Only tasks in entries on or after this date (YYYY-MM-DD)"))
+                  .arg(Arg::with_name("to")
+                       .long("to")
+                       .takes_value(true)
+                       .help("Only tasks in entries on or before this date (YYYY-MM-DD)")))
+             .subcommand(App::new("done")
+                  .about("Tick tasks off in the entries they're in")
+                  .arg(Arg::with_name("task")
+                       .required(true)
+                       .multiple_values(true)
+                       .help("Task ids as listed by `tasks list`, or words from an open task")))
+             .subcommand(App::new("add")
+                  .about("Add a task to today's entry")
+                  .arg(Arg::with_name("text")
+                       .required(true)
+                       .multiple_values(true)
+                       .help("What's to be done, #tags included"))
+                  .arg(Arg::with_name("due")
+                       .long("due")
+                       .takes_value(true)
+                       .help("Due date (YYYY-MM-DD)"))))
+        .subcommand(App::new("habits")
+             .about("Show habit streaks, completion and a heatmap, for the last four weeks by default")
+             .arg(Arg::with_name("from")
+                  .long("from")
+                  .takes_value(true)
+                  .help("First day of the report (YYYY-MM-DD)"))
+             .arg(Arg::with_name("to")
+                  .long("to")
+                  .takes_value(true)
+                  .help("Last day of the report (YYYY-MM-DD), today by default")))
+        .subcommand(App::new("stats")
+             .about("Chart your mood or energy over time, for the last four weeks by default")
+             .arg(Arg::with_name("metric")
+                  .required(true)
+                  .possible_values(["mood", "energy"])
+                  .help("What to chart"))
+             .arg(Arg::with_name("from")
+                  .long("from")
+                  .takes_value(true)
+                  .help("First day of the chart (YYYY-MM-DD)"))
+             .arg(Arg::with_name("to")
+                  .long("to")
+                  .takes_value(true)
+                  .help("Last day of the chart (YYYY-MM-DD), today by default")))
+        .subcommand(App::new("sync")
+             .about("Send entries saved while the storage was unreachable, and refresh the local index"))
+        .subcommand(App::new("config")
+             .about("Read or change a setting, --config runs the interactive setup")
+             .subcommand_required(true)
+             .arg_required_else_help(true)
+             .subcommand(App::new("get")
+                  .about("Print a setting, or all of them")
+                  .arg(Arg::with_name("key")
+                       .help("Dotted setting name, e.g. editor.command")))
+             .subcommand(App::new("set")
+                  .about("Change a setting")
+                  .arg(Arg::with_name("key")
+                       .required(true)
+                       .help("Dotted setting name, e.g. editor_frequency_minutes"))
+                  .arg(Arg::with_name("value")
+                       .required(true)
+                       .allow_hyphen_values(true)
+                       .help("New value, as JSON or plain text"))))
+        .subcommand(App::new("search")
+             .about("Search the diary, supports \"phrases\", AND/OR/NOT and -word")
+             .arg(Arg::with_name("query")
+                  .required(true)
+                  .multiple_values(true)
+                  .help("Words to look for"))
+             .arg(Arg::with_name("from")
+                  .long("from")
+                  .takes_value(true)
+                  .help("Only entries on or after this date (YYYY-MM-DD)"))
+             .arg(Arg::with_name("to")
+                  .long("to")
+                  .takes_value(true)
+                  .help("Only entries on or before this date (YYYY-MM-DD)"))
+             .arg(Arg::with_name("limit")
+                  .long("limit")
+                  .takes_value(true)
+                  .default_value("20")
+                  .help("Maximum number of matches to show")))
+        .subcommand(App::new("history")
+             .about("List earlier revisions of an entry, or print one with --rev")
+             .arg(Arg::with_name("date")
+                  .required(true)
+                  .help("Entry date (YYYY-MM-DD)"))
+             .arg(Arg::with_name("rev")
+                  .long("rev")
+                  .takes_value(true)
+                  .help("Revision number to print, 1 is the most recent")))
+        .subcommand(App::new("restore")
+             .about("Bring back an earlier revision of an entry")
+             .arg(Arg::with_name("date")
+                  .required(true)
+                  .help("Entry date (YYYY-MM-DD)"))
+             .arg(Arg::with_name("rev")
+                  .long("rev")
+                  .takes_value(true)
+                  .required(true)
+                  .help("Revision number, as listed by `history`")))
+        .subcommand(App::new("open")
+             .about("Open the editor now, through the running instance when there is one")
+             .arg(Arg::with_name("date")
+                  .help("Entry date (YYYY-MM-DD) to edit instead of today's")))
+        .subcommand(App::new("snooze")
+             .about("Put the next prompt of the running instance off")
+             .arg(Arg::with_name("minutes")
+                  .default_value("15")
+                  .help("Minutes to snooze for")))
+        .subcommand(App::new("skip")
+             .about("Let the running instance skip its next prompt"))
+        .subcommand(App::new("reload")
+             .about("Make the running instance re-read its configuration"))
+        .subcommand(App::new("status")
+             .about("Show what the running instance is up to"))
+        .subcommand(App::new("quit")
+             .about("Stop the running instance"))
+        .subcommand(App::new("add")
+             .about("Add a timestamped note to today's entry without opening the editor")
+             .arg(Arg::with_name("note")
+                  .required(true)
+                  .multiple_values(true)
+                  .allow_hyphen_values(true)
+                  .help("The note, or - to read it from stdin")))
+        .subcommand(App::new("client")
+             .about("Open the editor for the running instance whenever a prompt is due, run it in your desktop session"))
         .get_matches();
 
+    if let Some(command) = control_command(&matches)? {
+        match control::send(command).await? {
+            Some(reply) => println!("{}", reply),
+            None => println!("Diary App isn't running"),
+        }
+        return Ok(());
+    }
+
+    if matches.subcommand_matches("client").is_some() {
+        return client::run_client().await;
+    }
+
     if matches.is_present("config") {
-        let existing_config = ConfigManager::load().unwrap_or_else(|_| None);
+        let existing_config = ConfigManager::load().unwrap_or(None);
         let new_config = SetupWizard::run(existing_config.as_ref()).await?;
         ConfigManager::save(&new_config)?;
         println!("Configuration updated successfully.");
-        // return Ok(());
-        // Let's keep running the app but again an instance might be running alread
-        // Let's add code to check if an instance is already running
-        // If not, continue from here
+        // Hand the new settings to an instance that's already running instead of starting another
+        if let Some(reply) = control::send(ControlCommand::Reload).await? {
+            println!("{}", reply);
+            return Ok(());
+        }
     }
 
     let config_load_attempt = ConfigManager::load();
 
     let config = match config_load_attempt {
         Ok(Some(config)) => {
-            println!("Using Existing Configuration");
+            // Kept off stdout, which `show`, `export` and `config get` write to
+            eprintln!("Using Existing Configuration");
             config
         }
         _ => {
@@ -57,6 +292,150 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
+    match matches.subcommand() {
+        Some(("search", search_matches)) => {
+            let options = SearchOptions {
+                query: search_matches
+                    .values_of("query")
+                    .unwrap()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                from: parse_date_arg(search_matches.value_of("from"))?,
+                to: parse_date_arg(search_matches.value_of("to"))?,
+                limit: search_matches.value_of_t("limit")?,
+            };
+            return run_search(&config, &options);
+        }
+        Some(("show", show_matches)) => {
+            let date = parse_date_arg(show_matches.value_of("date"))?;
+            let sections = match (
+                show_matches.value_of("section"),
+                show_matches.value_of("since"),
+            ) {
+                (Some(time), _) => Some(SectionFilter::At(
+                    NaiveTime::parse_from_str(time, "%H:%M")
+                        .map_err(|_| format!("Invalid time `{}`, use HH:MM", time))?,
+                )),
+                (None, Some(since)) => Some(SectionFilter::Since(parse_duration_arg(since)?)),
+                (None, None) => None,
+            };
+            return cli::run_show(&config, date, sections, show_matches.is_present("metadata"))
+                .await;
+        }
+        Some(("list", list_matches)) => {
+            let from = parse_date_arg(list_matches.value_of("from"))?;
+            let to = parse_date_arg(list_matches.value_of("to"))?;
+            return cli::run_list(&config, from, to).await;
+        }
+        Some(("open", open_matches)) => {
+            return cli::run_open(&config, parse_date_arg(open_matches.value_of("date"))?).await;
+        }
+        Some(("add", add_matches)) => {
+            let words = add_matches.values_of("note").unwrap().collect::<Vec<_>>();
+            let note = if words == ["-"] {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                words.join(" ")
+            };
+            return cli::run_add(&config, note).await;
+        }
+        Some(("export", export_matches)) => {
+            let format = match export_matches.value_of("format") {
+                Some("markdown") => ExportFormat::Markdown,
+                _ => ExportFormat::Json,
+            };
+            return cli::run_export(
+                &config,
+                parse_date_arg(export_matches.value_of("from"))?,
+                parse_date_arg(export_matches.value_of("to"))?,
+                format,
+                export_matches.value_of("output").map(std::path::Path::new),
+            )
+            .await;
+        }
+        Some(("import", import_matches)) => {
+            let input = std::path::Path::new(import_matches.value_of("input").unwrap());
+            return cli::run_import(&config, input, import_matches.is_present("overwrite")).await;
+        }
+        Some(("tasks", tasks_matches)) => {
+            return match tasks_matches.subcommand() {
+                Some(("list", list_matches)) => {
+                    cli::run_tasks_list(
+                        &config,
+                        parse_date_arg(list_matches.value_of("from"))?,
+                        parse_date_arg(list_matches.value_of("to"))?,
+                        list_matches.value_of("tag"),
+                        list_matches.is_present("all"),
+                    )
+                    .await
+                }
+                Some(("done", done_matches)) => {
+                    cli::run_tasks_done(&config, done_matches.values_of("task").unwrap().collect())
+                        .await
+                }
+                Some(("add", add_matches)) => {
+                    let text = add_matches
+                        .values_of("text")
+                        .unwrap()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let due = parse_date_arg(add_matches.value_of("due"))?;
+                    cli::run_tasks_add(&config, &text, due).await
+                }
+                _ => unreachable!("clap requires a tasks subcommand"),
+            };
+        }
+        Some(("habits", habits_matches)) => {
+            let from = parse_date_arg(habits_matches.value_of("from"))?;
+            let to = parse_date_arg(habits_matches.value_of("to"))?;
+            return cli::run_habits(&config, from, to).await;
+        }
+        Some(("stats", stats_matches)) => {
+            let metric = match stats_matches.value_of("metric") {
+                Some("energy") => StatsMetric::Energy,
+                _ => StatsMetric::Mood,
+            };
+            let from = parse_date_arg(stats_matches.value_of("from"))?;
+            let to = parse_date_arg(stats_matches.value_of("to"))?;
+            return cli::run_stats(&config, metric, from, to).await;
+        }
+        Some(("sync", _)) => return cli::run_sync(&config).await,
+        Some(("config", config_matches)) => {
+            return match config_matches.subcommand() {
+                Some(("get", get_matches)) => {
+                    cli::run_config_get(&config, get_matches.value_of("key"))
+                }
+                Some(("set", set_matches)) => {
+                    let key = set_matches.value_of("key").unwrap();
+                    cli::run_config_set(&config, key, set_matches.value_of("value").unwrap()).await
+                }
+                _ => unreachable!("clap requires a config subcommand"),
+            };
+        }
+        Some(("history", history_matches)) => {
+            let date = parse_date_arg(history_matches.value_of("date"))?.unwrap();
+            let rev = history_matches
+                .is_present("rev")
+                .then(|| history_matches.value_of_t("rev"))
+                .transpose()?;
+            return cli::run_history(&config, date, rev);
+        }
+        Some(("restore", restore_matches)) => {
+            let date = parse_date_arg(restore_matches.value_of("date"))?.unwrap();
+            return cli::run_restore(&config, date, restore_matches.value_of_t("rev")?).await;
+        }
+        _ => {}
+    }
+
+    println!("The |\\|/\\R\\| /\\PP");
+    let Some(lock) = InstanceLock::acquire()? else {
+        println!("Diary App is already running");
+        if let Some(status) = control::send(ControlCommand::Status).await? {
+            println!("{}", status);
+        }
+        return Ok(());
+    };
+
     println!("Setup Done");
     // Check if this is the first run and set up the service if needed
     println!("Regisering desktop auto start entry");
@@ -64,20 +443,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await
         .expect("Failed to set up Diary Service");
 
-    let storage: Box<dyn Storage> = match config.storage_type {
-        StorageType::Local => Box::new(LocalStorage::new(
-            dirs::home_dir()
-                .expect("Home Directory Not Found")
-                .join("Diary"),
-        )),
-        StorageType::Notion => Box::new(NotionStorage::new(
-            config.notion_token.unwrap(),
-            config.notion_database_id.unwrap(),
-        )),
-        // StorageType::GoogleDrive => Box::new(GoogleDriveStorage::new(config.google_drive_token.unwrap())),
-    };
-
-    println!("Got hold of Storage _/");
     /*
     // Daemonize the process
     let daemonize = Daemonize::new()
@@ -93,126 +458,118 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Err(e) => eprintln!("Error, {}", e),
     }*/
 
-    let weather_service = match std::env::var("WEATHER_API_KEY") {
-        Ok(api_key) => Some(OpenWeatherService::new(&config.city, &api_key)),
-        Err(_) => None,
-    };
-
-    //
-    // let dbus_connection = Connection::new_session()?;
-    // let dbus_proxy = dbus_connection.with_proxy("org.example.DiaryApp", false, true, false);
-    // let mut dbus_cr = Crossroads::new();
-    // dbus_connection.request_name("org.example.DiaryApp", false, true, false)?;
-    // let token = dbus_cr.register("com.ravinderpayal.DiaryApp", |b| {
-    //    b.method(
-    //        "LaunchEditor",
-    //        (),
-    //        (),
-    //        |_: dbus_crossroads::Context, _, _| {
-    //            // Signal to launch editor
-    //            Ok(())
-    //        },
-    //    );
-    // });
-
-    // dbus_cr.serve(&dbus_connection)?;
-    let mut weather_service = match std::env::var("WEATHER_API_KEY") {
-        Ok(api_key) => Some(OpenWeatherService::new(&config.city, &api_key)),
-        Err(_) => None,
+    let (requests, control) = mpsc::channel(8);
+    let (editor_clients, clients) = mpsc::channel(1);
+    lock.serve(requests.clone(), editor_clients)?;
+    let bus = match BusService::start(requests) {
+        Ok(bus) => Some(bus),
+        Err(err) => {
+            eprintln!("Not offering the D-Bus interface: {}", err);
+            None
+        }
     };
+    Daemon::new(config, bus)?.run(control, clients).await
+}
 
-    loop {
-        let now = Local::now();
-        let today = now.date_naive();
-        //  if now.hour() == 5 && now.minute() == 30 || now.hour() > 5 {
-        if storage.get_entry(today).await?.is_none() {
-            let mut city = get_ip_location().await?;
-            if city.len() > 0 {
-                weather_service = match std::env::var("WEATHER_API_KEY") {
-        Ok(api_key) => Some(OpenWeatherService::new(&city, &api_key)),
-        Err(_) => None,
-    };
-            } else {
-                city = config.city.clone();
+/// The subcommand as a command for the running instance, if it is one.
+fn control_command(
+    matches: &clap::ArgMatches,
+) -> Result<Option<ControlCommand>, Box<dyn std::error::Error>> {
+    Ok(match matches.subcommand() {
+        Some(("snooze", snooze_matches)) => {
+            let minutes: i64 = snooze_matches.value_of_t("minutes")?;
+            if !(1..=MAX_SNOOZE_MINUTES).contains(&minutes) {
+                return Err(format!("Snooze for 1 to {} minutes", MAX_SNOOZE_MINUTES).into());
             }
-
-
-            let weather = match &weather_service {
-                Some(s) => s.get_weather()?,
-                None => "API Not Configured".to_string(),
-            };
-            let content = format!(
-                    "🌆 City: {}\n🌤️ Weather: {}\n# Tasks for today\n- [ ] Eat Healthy\n- [ ] Workout\n- [ ] Talk to someone", city, weather
-                );
-            launch_editor(&storage, content).await?;
-            tokio::time::sleep(tokio::time::Duration::from_secs(
-                (60 * config.editor_frequency_minutes).into(),
-            ))
-            .await;
-        }
-        // }
-        else {
-            // Collect thoughts
-            let entry = storage.get_entry(today).await?.unwrap_or_default();
-            println!("Existing Content:`{}`", &entry);
-            let new_content = format!("{}\n\n## {}\n\n", entry, now.format("%H:%M"));
-
-            // let temp_file = tempfile::NamedTempFile::new()?;
-            // std::fs::write(temp_file.path(), new_content)?;
-            // Open the default text editor
-
-            // dbus_connection.send(dbus::Message::new_method_call(
-            //    "com.ravinderpayal.DiaryApp",
-            //    "/org/example/DiaryApp",
-            //    "com.ravinderpayal.DiaryApp",
-            //    "LaunchEditor",
-            // )?);
-
-            // let updated_content = std::fs::read_to_string(temp_file.path())?;
-
-            launch_editor(&storage, new_content).await?;
-            tokio::time::sleep(tokio::time::Duration::from_secs(
-                (60 * config.editor_frequency_minutes).into(),
-            ))
-            .await;
+            Some(ControlCommand::Snooze(Duration::minutes(minutes)))
         }
-    }
+        Some(("skip", _)) => Some(ControlCommand::Skip),
+        Some(("reload", _)) => Some(ControlCommand::Reload),
+        Some(("status", _)) => Some(ControlCommand::Status),
+        Some(("quit", _)) => Some(ControlCommand::Quit),
+        _ => None,
+    })
 }
 
+fn parse_date_arg(value: Option<&str>) -> Result<Option<NaiveDate>, Box<dyn std::error::Error>> {
+    value
+        .map(|date| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| format!("Invalid date `{}`, use YYYY-MM-DD", date).into())
+        })
+        .transpose()
+}
 
-async fn launch_editor(
-    storage: &Box<dyn Storage>,
-    new_content: String
-) -> Result<(), Box<dyn std::error::Error>> {
-    let now = Local::now();
-    let today = now.date_naive();
-
-    let temp_file = tempfile::NamedTempFile::new()?;
-    std::fs::write(temp_file.path(), new_content)?;
-    // Open the default text editor
-    if cfg!(target_os = "windows") {
-        std::process::Command::new("notepad")
-            .arg(temp_file.path())
-            .status()?;
-    } else {
-        println!("Opening editor for recording regular response");
-        std::process::Command::new("x-terminal-emulator")
-            .arg("-e")
-            .arg("vim")
-            .arg(temp_file.path())
-            .env("DISPLAY", ":0".to_string())
-            .status().expect("Failed to open vim for recoriding input");
-        /*std::process::Command::new("x-terminal-emulator")
-        .arg("-e")
-        .arg("vim")
-        .arg(temp_file.path())
-        .status()
-        .expect("Failed to open vim for tasks in foreground");*/
+/// Reads a duration like `2h`, `45m` or `1h30m`.
+fn parse_duration_arg(value: &str) -> Result<Duration, Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid duration `{}`, use e.g. 2h, 45m or 1h30m", value);
+    let captures = regex::Regex::new(r"^(?:(\d+)h)?\s*(?:(\d+)m)?$")
+        .unwrap()
+        .captures(value.trim())
+        .filter(|captures| captures.get(1).is_some() || captures.get(2).is_some())
+        .ok_or_else(invalid)?;
+    let number = |group| {
+        captures
+            .get(group)
+            .map_or(Some(0), |n| n.as_str().parse().ok())
     };
+    match (number(1), number(2)) {
+        (Some(hours), Some(minutes)) => Ok(Duration::hours(hours) + Duration::minutes(minutes)),
+        _ => Err(invalid().into()),
+    }
+}
 
-    let updated_content = std::fs::read_to_string(temp_file.path())?;
-    storage.save_entry(today, &updated_content).await?;
-    Ok(())
+/// The configured storage with the SQLite index and history kept around it.
+fn build_storage(config: &Config) -> Result<Box<dyn Storage>, Box<dyn std::error::Error>> {
+    let sqlite_path = ConfigManager::data_dir().join("diary.sqlite3");
+    let storage = build_backend(config)?;
+    let storage: Box<dyn Storage> =
+        if config.sqlite_index && !matches!(config.storage_type, StorageType::Sqlite) {
+            Box::new(SqliteStorage::with_backend(sqlite_path, storage)?)
+        } else {
+            storage
+        };
+    Ok(Box::new(HistoryStorage::new(
+        entry_history(config),
+        storage,
+    )))
 }
 
+/// Just the configured storage, where entries are kept for good.
+fn build_backend(config: &Config) -> Result<Box<dyn Storage>, Box<dyn std::error::Error>> {
+    let sqlite_path = ConfigManager::data_dir().join("diary.sqlite3");
+    let storage: Box<dyn Storage> = match config.storage_type {
+        StorageType::Local => Box::new(LocalStorage::new(
+            dirs::home_dir()
+                .expect("Home Directory Not Found")
+                .join("Diary"),
+        )),
+        StorageType::Notion => Box::new(NotionStorage::new(
+            config
+                .notion_token
+                .clone()
+                .ok_or("Notion token is not configured")?,
+            config
+                .notion_database_id
+                .clone()
+                .ok_or("Notion database is not configured")?,
+        )),
+        StorageType::Vault => Box::new(VaultStorage::new(
+            config
+                .vault_path
+                .clone()
+                .ok_or("Vault path is not configured")?
+                .into(),
+        )),
+        StorageType::Sqlite => Box::new(SqliteStorage::new(sqlite_path)?),
+        // StorageType::GoogleDrive => Box::new(GoogleDriveStorage::new(config.google_drive_token.unwrap())),
+    };
+    Ok(storage)
+}
 
+fn entry_history(config: &Config) -> History {
+    History::new(
+        ConfigManager::data_dir().join("history"),
+        config.history.clone(),
+    )
+}
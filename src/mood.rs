@@ -0,0 +1,45 @@
+// mood.rs
+use diary_app::MOOD_LINE;
+use regex::Regex;
+
+/// The line a check-in offers when `mood.prompt` is on, filled in as e.g. `mood: 4 energy: 2`.
+pub const MOOD_PROMPT: &str = "mood:  energy: \n\n";
+
+/// Takes out prompt lines left without an answer, so skipping the prompt leaves no trace.
+pub fn drop_unanswered(content: &str) -> String {
+    let mood_line = Regex::new(MOOD_LINE).unwrap();
+    content
+        .split_inclusive('\n')
+        .filter(|line| {
+            let line = line.trim_end_matches(['\r', '\n']);
+            let Some(captures) = mood_line.captures(line) else {
+                return true;
+            };
+            // Blank lines match too, they have neither field
+            let fields: Vec<&str> = captures
+                .iter()
+                .skip(1)
+                .flatten()
+                .map(|field| field.as_str())
+                .collect();
+            fields.is_empty() || fields.iter().any(|value| !value.is_empty())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_blank_prompts_only() {
+        let content = format!(
+            "## 15:00\n\n{}Wrote something.\n\nmood: 3 energy:\n",
+            MOOD_PROMPT
+        );
+        assert_eq!(
+            drop_unanswered(&content),
+            "## 15:00\n\n\nWrote something.\n\nmood: 3 energy:\n"
+        );
+    }
+}
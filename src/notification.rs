@@ -0,0 +1,160 @@
+// notification.rs
+use chrono::Duration;
+use dbus::arg::PropMap;
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use dbus::Message;
+use std::error::Error;
+use std::sync::mpsc as std_mpsc;
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+use crate::scheduler::{Prompt, ReminderAction};
+
+const SERVICE: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+const DBUS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// An unanswered notification is taken back after this long.
+const ANSWER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+/// The snooze buttons, as action key, label and minutes.
+const SNOOZES: [(&str, &str, i64); 3] = [
+    ("snooze5", "5 min", 5),
+    ("snooze15", "15 min", 15),
+    ("snooze60", "1 hour", 60),
+];
+
+/// What the user did with the notification.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Answer {
+    Open,
+    Later(ReminderAction),
+    /// Closed or ignored without picking anything
+    Dismissed,
+}
+
+/// Shows "Time to journal" with Open, three snooze lengths and Skip as buttons. The answer arrives on
+/// the returned channel once the user acts on it, or it closes or times out.
+pub fn ask(prompt: Prompt) -> Result<oneshot::Receiver<Answer>, Box<dyn Error>> {
+    let connection = Connection::new_session()?;
+
+    // Listen before showing it, a quick click mustn't get lost
+    let (signals, received) = std_mpsc::channel();
+    for member in ["ActionInvoked", "NotificationClosed"] {
+        let rule = MatchRule::new_signal(SERVICE, member);
+        connection.add_match_no_cb(&rule.match_str())?;
+        let signals = signals.clone();
+        use dbus::channel::MatchingReceiver;
+        connection.start_receive(
+            rule,
+            Box::new(move |message, _| {
+                let _ = signals.send(message);
+                true
+            }),
+        );
+    }
+
+    let body = match prompt {
+        Prompt::Morning => "A new day, your morning entry is waiting",
+        Prompt::CheckIn => "How is it going? Note down what's on your mind",
+    };
+    let mut actions = vec!["default", "Open", "open", "Open"];
+    for (key, label, _) in SNOOZES {
+        actions.extend([key, label]);
+    }
+    actions.extend(["skip", "Skip"]);
+    let proxy = connection.with_proxy(SERVICE, PATH, DBUS_TIMEOUT);
+    let (id,): (u32,) = proxy.method_call(
+        SERVICE,
+        "Notify",
+        (
+            "Diary App",
+            0u32,
+            "accessories-text-editor",
+            "Time to journal",
+            body,
+            actions,
+            PropMap::new(),
+            -1i32,
+        ),
+    )?;
+
+    let (sender, answer) = oneshot::channel();
+    std::thread::spawn(move || {
+        let answer = wait_for_answer(&connection, id, received).unwrap_or_else(|err| {
+            eprintln!("Lost track of the notification: {}", err);
+            Answer::Dismissed
+        });
+        let _ = sender.send(answer);
+    });
+    Ok(answer)
+}
+
+fn wait_for_answer(
+    connection: &Connection,
+    id: u32,
+    signals: std_mpsc::Receiver<Message>,
+) -> Result<Answer, Box<dyn Error>> {
+    let deadline = Instant::now() + ANSWER_TIMEOUT;
+    while Instant::now() < deadline {
+        connection.process(std::time::Duration::from_secs(1))?;
+        for message in signals.try_iter() {
+            match message.member().as_deref() {
+                Some("ActionInvoked") => {
+                    let (notification, action): (u32, String) = message.read2()?;
+                    if notification == id {
+                        return Ok(answer_for(&action));
+                    }
+                }
+                Some("NotificationClosed") => {
+                    let (notification, _reason): (u32, u32) = message.read2()?;
+                    if notification == id {
+                        return Ok(Answer::Dismissed);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let proxy = connection.with_proxy(SERVICE, PATH, DBUS_TIMEOUT);
+    let _: Result<(), _> = proxy.method_call(SERVICE, "CloseNotification", (id,));
+    Ok(Answer::Dismissed)
+}
+
+fn answer_for(action: &str) -> Answer {
+    match action {
+        "default" | "open" => Answer::Open,
+        "skip" => Answer::Later(ReminderAction::Skip),
+        _ => SNOOZES
+            .iter()
+            .find(|(key, _, _)| *key == action)
+            .map(|(_, _, minutes)| {
+                Answer::Later(ReminderAction::Snooze(Duration::minutes(*minutes)))
+            })
+            .unwrap_or(Answer::Dismissed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_each_button_to_its_answer() {
+        assert_eq!(answer_for("default"), Answer::Open);
+        assert_eq!(
+            answer_for("snooze5"),
+            Answer::Later(ReminderAction::Snooze(Duration::minutes(5)))
+        );
+        assert_eq!(
+            answer_for("snooze15"),
+            Answer::Later(ReminderAction::Snooze(Duration::minutes(15)))
+        );
+        assert_eq!(
+            answer_for("snooze60"),
+            Answer::Later(ReminderAction::Snooze(Duration::hours(1)))
+        );
+        assert_eq!(answer_for("skip"), Answer::Later(ReminderAction::Skip));
+        assert_eq!(answer_for("snooze"), Answer::Dismissed);
+    }
+}
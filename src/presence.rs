@@ -0,0 +1,253 @@
+// presence.rs
+use chrono::{Duration, NaiveDateTime};
+use dbus::blocking::SyncConnection;
+use diary_app::IdleConfig;
+use std::sync::Arc;
+
+const DBUS_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+/// How often a held back prompt looks at the session again.
+pub const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Tells whether the user is at the keyboard. Either answer may be `None` when the
+/// detector has no way of knowing, so detectors can be stacked. Asking may block, so
+/// it's done off the async runtime.
+pub trait PresenceDetector: Send + Sync {
+    /// Time since the last keyboard or mouse input
+    fn idle_time(&self) -> Option<Duration>;
+    fn screen_locked(&self) -> Option<bool>;
+}
+
+/// `org.gnome.Mutter.IdleMonitor`, idle time on GNOME Shell.
+pub struct MutterIdleMonitor {
+    connection: Arc<SyncConnection>,
+}
+
+impl PresenceDetector for MutterIdleMonitor {
+    fn idle_time(&self) -> Option<Duration> {
+        let proxy = self.connection.with_proxy(
+            "org.gnome.Mutter.IdleMonitor",
+            "/org/gnome/Mutter/IdleMonitor/Core",
+            DBUS_TIMEOUT,
+        );
+        let (idle_ms,): (u64,) = proxy
+            .method_call("org.gnome.Mutter.IdleMonitor", "GetIdletime", ())
+            .ok()?;
+        Some(Duration::milliseconds(idle_ms as i64))
+    }
+
+    fn screen_locked(&self) -> Option<bool> {
+        None
+    }
+}
+
+/// The `ScreenSaver` interface, as served by KDE and others under
+/// `org.freedesktop.ScreenSaver` and by GNOME under `org.gnome.ScreenSaver`.
+pub struct ScreenSaver {
+    connection: Arc<SyncConnection>,
+    service: &'static str,
+    path: &'static str,
+}
+
+impl PresenceDetector for ScreenSaver {
+    fn idle_time(&self) -> Option<Duration> {
+        let proxy = self
+            .connection
+            .with_proxy(self.service, self.path, DBUS_TIMEOUT);
+        let (idle_seconds,): (u32,) = proxy
+            .method_call(self.service, "GetSessionIdleTime", ())
+            .ok()?;
+        Some(Duration::seconds(idle_seconds.into()))
+    }
+
+    fn screen_locked(&self) -> Option<bool> {
+        let proxy = self
+            .connection
+            .with_proxy(self.service, self.path, DBUS_TIMEOUT);
+        let (active,): (bool,) = proxy.method_call(self.service, "GetActive", ()).ok()?;
+        Some(active)
+    }
+}
+
+/// Asks each detector in turn and goes with the first one that knows.
+pub struct StackedDetector {
+    detectors: Vec<Box<dyn PresenceDetector>>,
+}
+
+impl StackedDetector {
+    pub fn new(detectors: Vec<Box<dyn PresenceDetector>>) -> Self {
+        StackedDetector { detectors }
+    }
+
+    /// The desktop session detectors, or none at all without a session bus.
+    pub fn session() -> Self {
+        let connection = match SyncConnection::new_session() {
+            Ok(connection) => Arc::new(connection),
+            Err(err) => {
+                eprintln!("No session bus, prompting without idle detection: {}", err);
+                return Self::new(Vec::new());
+            }
+        };
+        Self::new(vec![
+            Box::new(MutterIdleMonitor {
+                connection: connection.clone(),
+            }),
+            Box::new(ScreenSaver {
+                connection: connection.clone(),
+                service: "org.gnome.ScreenSaver",
+                path: "/org/gnome/ScreenSaver",
+            }),
+            Box::new(ScreenSaver {
+                connection,
+                service: "org.freedesktop.ScreenSaver",
+                path: "/org/freedesktop/ScreenSaver",
+            }),
+        ])
+    }
+}
+
+impl PresenceDetector for StackedDetector {
+    fn idle_time(&self) -> Option<Duration> {
+        self.detectors.iter().find_map(|d| d.idle_time())
+    }
+
+    fn screen_locked(&self) -> Option<bool> {
+        self.detectors.iter().find_map(|d| d.screen_locked())
+    }
+}
+
+/// Holds a due prompt back while the screen is locked or the user is busy typing.
+pub struct PresenceGate {
+    detector: Arc<dyn PresenceDetector>,
+    config: IdleConfig,
+    deferred_since: Option<NaiveDateTime>,
+}
+
+impl PresenceGate {
+    pub fn new(detector: Arc<dyn PresenceDetector>, config: IdleConfig) -> Self {
+        PresenceGate {
+            detector,
+            config,
+            deferred_since: None,
+        }
+    }
+
+    /// Takes on changed settings, keeping the detector and any prompt held back.
+    pub fn reconfigure(&mut self, config: IdleConfig) {
+        self.config = config;
+    }
+
+    /// Whether a prompt due now may be shown.
+    ///
+    /// Never while the screen is locked. While the user is active it waits for a pause
+    /// of `pause_seconds`, but no longer than `max_defer_minutes` in total.
+    pub async fn ready(&mut self, now: NaiveDateTime) -> bool {
+        if !self.config.enabled {
+            return true;
+        }
+        let detector = self.detector.clone();
+        let (locked, idle) = tokio::task::spawn_blocking(move || match detector.screen_locked() {
+            Some(true) => (true, None),
+            _ => (false, detector.idle_time()),
+        })
+        .await
+        // Nothing known when the lookup fell over
+        .unwrap_or((false, None));
+        if locked {
+            self.deferred_since.get_or_insert(now);
+            return false;
+        }
+
+        let paused = idle
+            .map(|idle| idle >= Duration::seconds(self.config.pause_seconds.into()))
+            .unwrap_or(true);
+        let deferred_since = *self.deferred_since.get_or_insert(now);
+        let waited_enough =
+            now - deferred_since >= Duration::minutes(self.config.max_defer_minutes.into());
+        if paused || waited_enough {
+            self.deferred_since = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::sync::Mutex;
+
+    /// Reports whatever the test set last, as lock state and idle time.
+    #[derive(Default)]
+    struct FakeDetector(Mutex<(Option<bool>, Option<Duration>)>);
+
+    impl FakeDetector {
+        fn set(&self, locked: bool, idle_seconds: i64) {
+            *self.0.lock().unwrap() = (Some(locked), Some(Duration::seconds(idle_seconds)));
+        }
+    }
+
+    impl PresenceDetector for FakeDetector {
+        fn idle_time(&self) -> Option<Duration> {
+            self.0.lock().unwrap().1
+        }
+
+        fn screen_locked(&self) -> Option<bool> {
+            self.0.lock().unwrap().0
+        }
+    }
+
+    fn at(minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 5)
+            .unwrap()
+            .and_hms_opt(10, minute, 0)
+            .unwrap()
+    }
+
+    fn gate() -> (PresenceGate, Arc<FakeDetector>) {
+        let detector = Arc::new(FakeDetector::default());
+        let gate = PresenceGate::new(detector.clone(), IdleConfig::default());
+        (gate, detector)
+    }
+
+    #[tokio::test]
+    async fn waits_while_the_screen_is_locked() {
+        let (mut gate, detector) = gate();
+        detector.set(true, 600);
+        assert!(!gate.ready(at(0)).await);
+        // However long it takes
+        assert!(!gate.ready(at(59)).await);
+
+        detector.set(false, 600);
+        assert!(gate.ready(at(59)).await);
+    }
+
+    #[tokio::test]
+    async fn defers_for_a_pause_until_it_has_waited_long_enough() {
+        let (mut gate, detector) = gate();
+        detector.set(false, 5);
+        assert!(!gate.ready(at(0)).await);
+        assert!(!gate.ready(at(14)).await);
+        // Busy all along, it fires once max_defer_minutes are up
+        assert!(gate.ready(at(15)).await);
+
+        // And the next prompt starts waiting afresh
+        assert!(!gate.ready(at(16)).await);
+        detector.set(false, 20);
+        assert!(gate.ready(at(17)).await);
+        detector.set(false, 5);
+        assert!(!gate.ready(at(30)).await);
+    }
+
+    #[tokio::test]
+    async fn takes_on_new_settings() {
+        let (mut gate, detector) = gate();
+        detector.set(true, 0);
+        gate.reconfigure(IdleConfig {
+            enabled: false,
+            ..IdleConfig::default()
+        });
+        assert!(gate.ready(at(0)).await);
+    }
+}
@@ -0,0 +1,423 @@
+// scheduler.rs
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use cron::Schedule;
+use diary_app::{Config, ScheduleConfig};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Longest single sleep, so a suspended laptop notices the missed slot soon after waking.
+const MAX_SLEEP_SECONDS: i64 = 60;
+/// How many days ahead to look for a slot the schedule allows before giving up.
+const MAX_LOOKAHEAD_DAYS: usize = 366;
+/// The longest a prompt can be put off for, a week.
+pub const MAX_SNOOZE_MINUTES: i64 = 7 * 24 * 60;
+
+/// Source of the current time, swapped out to drive the scheduler from a fixed clock.
+pub trait Clock {
+    fn now(&self) -> NaiveDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prompt {
+    /// First prompt of a diary day, opens the morning template
+    Morning,
+    /// Appends a `## HH:MM` section to the day's entry
+    CheckIn,
+}
+
+impl std::fmt::Display for Prompt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Prompt::Morning => write!(f, "morning"),
+            Prompt::CheckIn => write!(f, "check-in"),
+        }
+    }
+}
+
+/// What the user chose instead of writing when a prompt came up.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReminderAction {
+    /// Ask again after this long
+    Snooze(#[serde(with = "crate::control::minutes")] Duration),
+    /// Let this slot pass, the next one follows the usual cadence
+    Skip,
+}
+
+impl std::fmt::Display for ReminderAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReminderAction::Snooze(duration) => write!(f, "snoozed {} min", duration.num_minutes()),
+            ReminderAction::Skip => write!(f, "skipped"),
+        }
+    }
+}
+
+/// Works out which diary day it is and when the next prompt is due.
+///
+/// A diary day runs from `day_start_time` to the same time next morning, so with a
+/// 05:30 start, 01:00 still belongs to yesterday's entry.
+pub struct Scheduler<C: Clock = SystemClock> {
+    clock: C,
+    day_start: NaiveTime,
+    frequency: Duration,
+    schedule: ScheduleConfig,
+    cron: Vec<Schedule>,
+    last_prompt: Option<NaiveDateTime>,
+    snoozed_until: Option<NaiveDateTime>,
+}
+
+impl Scheduler<SystemClock> {
+    pub fn new(config: &Config) -> Self {
+        Self::with_clock(SystemClock, config)
+    }
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn with_clock(clock: C, config: &Config) -> Self {
+        let mut scheduler = Scheduler {
+            clock,
+            day_start: config.day_start_time,
+            frequency: Duration::zero(),
+            schedule: ScheduleConfig::default(),
+            cron: Vec::new(),
+            last_prompt: None,
+            snoozed_until: None,
+        };
+        scheduler.reconfigure(config);
+        scheduler
+    }
+
+    /// Picks up changed settings, keeping track of the prompts already shown.
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.day_start = config.day_start_time;
+        self.frequency = Duration::minutes(config.editor_frequency_minutes.max(1).into());
+        self.schedule = config.schedule.clone();
+        self.cron = config
+            .schedule
+            .cron
+            .iter()
+            .filter_map(|expression| match parse_cron(expression) {
+                Ok(schedule) => Some(schedule),
+                Err(err) => {
+                    eprintln!("Ignoring cron expression `{}`: {}", expression, err);
+                    None
+                }
+            })
+            .collect();
+    }
+
+    pub fn now(&self) -> NaiveDateTime {
+        self.clock.now()
+    }
+
+    /// The diary date for the current moment.
+    pub fn logical_date(&self) -> NaiveDate {
+        self.logical_date_at(self.clock.now())
+    }
+
+    pub fn logical_date_at(&self, at: NaiveDateTime) -> NaiveDate {
+        if at.time() < self.day_start {
+            at.date().pred_opt().expect("date out of range")
+        } else {
+            at.date()
+        }
+    }
+
+    /// When the diary day containing `at` began.
+    pub fn day_start_of(&self, at: NaiveDateTime) -> NaiveDateTime {
+        self.logical_date_at(at).and_time(self.day_start)
+    }
+
+    /// The next prompt and when it is due, given whether today's entry already exists.
+    ///
+    /// Check-ins follow the configured fixed times or cron expressions, or else every
+    /// `editor_frequency_minutes` after the last prompt. Without an entry the morning
+    /// prompt is due straight away, and retried at the same cadence if it was dismissed.
+    /// A snoozed prompt comes back when the snooze is over. Once the next day starts its
+    /// morning prompt takes over. Every slot is pushed past quiet hours, disallowed
+    /// weekdays and skip dates.
+    pub fn next_prompt(&self, has_entry: bool) -> (NaiveDateTime, Prompt) {
+        let next_day_start = self.day_start_of(self.clock.now()) + Duration::days(1);
+        let due = self.due(has_entry);
+        if due >= next_day_start {
+            (self.next_allowed(next_day_start), Prompt::Morning)
+        } else if has_entry {
+            (due, Prompt::CheckIn)
+        } else {
+            (due, Prompt::Morning)
+        }
+    }
+
+    /// When the pending prompt is due, before the next day's morning prompt is considered.
+    fn due(&self, has_entry: bool) -> NaiveDateTime {
+        let now = self.clock.now();
+        let day_start = self.day_start_of(now);
+        let due = match (
+            self.snoozed_until,
+            self.last_prompt.filter(|l| *l >= day_start),
+        ) {
+            (Some(until), _) => until,
+            (None, Some(last)) => self.next_slot(last),
+            (None, None) if has_entry && self.has_slots() => self.next_slot(now),
+            (None, None) => now,
+        };
+        self.next_allowed(due)
+    }
+
+    fn has_slots(&self) -> bool {
+        !self.schedule.fixed_times.is_empty() || !self.cron.is_empty()
+    }
+
+    /// The first check-in slot after `after`.
+    fn next_slot(&self, after: NaiveDateTime) -> NaiveDateTime {
+        if !self.has_slots() {
+            return after + self.frequency;
+        }
+        let fixed = (0..2).flat_map(|offset| {
+            let date = after.date() + Duration::days(offset);
+            self.schedule
+                .fixed_times
+                .iter()
+                .map(move |t| date.and_time(*t))
+        });
+        let cron = self.cron.iter().filter_map(|schedule| {
+            let after = Local.from_local_datetime(&after).earliest()?;
+            schedule.after(&after).next().map(|at| at.naive_local())
+        });
+        fixed
+            .chain(cron)
+            .filter(|at| *at > after)
+            .min()
+            .unwrap_or(after + self.frequency)
+    }
+
+    /// Moves `at` forward to the first moment the schedule allows a reminder.
+    pub fn next_allowed(&self, at: NaiveDateTime) -> NaiveDateTime {
+        let schedule = &self.schedule;
+        let mut at = at;
+        for _ in 0..MAX_LOOKAHEAD_DAYS * 2 {
+            let date = at.date();
+            let midnight_after = (date + Duration::days(1)).and_time(NaiveTime::MIN);
+            if schedule.skip_dates.contains(&date)
+                || (!schedule.days.is_empty() && !schedule.days.contains(&date.weekday()))
+            {
+                at = midnight_after;
+                continue;
+            }
+            let (Some(from), Some(until)) = (schedule.active_from, schedule.active_until) else {
+                return at;
+            };
+            let time = at.time();
+            if from <= until {
+                if time < from {
+                    at = date.and_time(from);
+                } else if time >= until {
+                    at = midnight_after;
+                    continue;
+                }
+            } else if time >= until && time < from {
+                // Active hours wrap past midnight, the gap is in the middle of the day
+                at = date.and_time(from);
+            }
+            return at;
+        }
+        eprintln!("The reminder schedule doesn't allow any slot in the coming year");
+        at
+    }
+
+    /// Remembers that a prompt was just shown, check-ins are counted from here.
+    pub fn record_prompt(&mut self) {
+        self.last_prompt = Some(self.clock.now());
+        self.snoozed_until = None;
+    }
+
+    /// Reschedules after the user snoozed or skipped the current prompt.
+    pub fn apply(&mut self, action: ReminderAction) {
+        match action {
+            ReminderAction::Snooze(duration) => {
+                let duration = duration.min(Duration::minutes(MAX_SNOOZE_MINUTES));
+                self.snoozed_until = Some(self.clock.now() + duration);
+            }
+            ReminderAction::Skip => {
+                // Skipped ahead of time, it's the upcoming slot that's passed over
+                let due = self.due(true);
+                self.record_prompt();
+                if due > self.clock.now() {
+                    self.last_prompt = Some(due);
+                }
+            }
+        }
+    }
+
+    /// How long to sleep towards `at`. Long waits are cut short so the caller can look
+    /// at the schedule again.
+    pub fn nap_towards(&self, at: NaiveDateTime) -> std::time::Duration {
+        (at - self.clock.now())
+            .min(Duration::seconds(MAX_SLEEP_SECONDS))
+            .to_std()
+            .unwrap_or_default()
+    }
+}
+
+/// Appends `action` to the reminder log, one line per snooze or skip.
+pub fn record_action(
+    log_path: &Path,
+    at: NaiveDateTime,
+    action: ReminderAction,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    writeln!(log, "{} {}", at.format("%Y-%m-%d %H:%M:%S"), action)?;
+    Ok(())
+}
+
+/// Parses a cron expression, accepting the usual five fields as well as the crate's
+/// own format with seconds first.
+fn parse_cron(expression: &str) -> Result<Schedule, cron::error::Error> {
+    if expression.split_whitespace().count() == 5 {
+        Schedule::from_str(&format!("0 {}", expression))
+    } else {
+        Schedule::from_str(expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Weekday;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A clock that stays where the test puts it.
+    #[derive(Clone)]
+    struct FixedClock(Rc<Cell<NaiveDateTime>>);
+
+    impl FixedClock {
+        fn at(at: NaiveDateTime) -> Self {
+            FixedClock(Rc::new(Cell::new(at)))
+        }
+
+        fn set(&self, at: NaiveDateTime) {
+            self.0.set(at);
+        }
+    }
+
+    impl Clock for FixedClock {
+        fn now(&self) -> NaiveDateTime {
+            self.0.get()
+        }
+    }
+
+    /// A Tuesday.
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 5)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn scheduler_at(now: NaiveDateTime, config: &Config) -> (Scheduler<FixedClock>, FixedClock) {
+        let clock = FixedClock::at(now);
+        (Scheduler::with_clock(clock.clone(), config), clock)
+    }
+
+    #[test]
+    fn early_hours_belong_to_the_day_before() {
+        let (scheduler, _) = scheduler_at(at(1, 0), &Config::new());
+        assert_eq!(
+            scheduler.logical_date(),
+            NaiveDate::from_ymd_opt(2024, 3, 4).unwrap()
+        );
+        assert_eq!(scheduler.logical_date_at(at(5, 30)), at(5, 30).date());
+    }
+
+    #[test]
+    fn prompts_for_the_morning_entry_then_checks_in() {
+        let (mut scheduler, clock) = scheduler_at(at(9, 0), &Config::new());
+        assert_eq!(scheduler.next_prompt(false), (at(9, 0), Prompt::Morning));
+
+        scheduler.record_prompt();
+        clock.set(at(9, 20));
+        assert_eq!(scheduler.next_prompt(true), (at(10, 0), Prompt::CheckIn));
+        // Dismissed without writing, the morning prompt comes back
+        assert_eq!(scheduler.next_prompt(false), (at(10, 0), Prompt::Morning));
+    }
+
+    #[test]
+    fn keeps_to_the_allowed_hours_and_days() {
+        let mut config = Config::new();
+        config.schedule.active_from = NaiveTime::from_hms_opt(9, 0, 0);
+        config.schedule.active_until = NaiveTime::from_hms_opt(21, 0, 0);
+        let (scheduler, _) = scheduler_at(at(6, 0), &config);
+        assert_eq!(scheduler.next_allowed(at(6, 0)), at(9, 0));
+        assert_eq!(scheduler.next_allowed(at(12, 0)), at(12, 0));
+        assert_eq!(
+            scheduler.next_allowed(at(22, 0)),
+            at(9, 0) + Duration::days(1)
+        );
+
+        // Tuesday is left out and Wednesday skipped, so it's Thursday morning
+        config.schedule.days = vec![Weekday::Mon, Weekday::Wed, Weekday::Thu];
+        config.schedule.skip_dates = vec![NaiveDate::from_ymd_opt(2024, 3, 6).unwrap()];
+        let (scheduler, _) = scheduler_at(at(6, 0), &config);
+        assert_eq!(
+            scheduler.next_allowed(at(12, 0)),
+            at(9, 0) + Duration::days(2)
+        );
+    }
+
+    #[test]
+    fn snoozing_and_skipping_reschedule() {
+        let (mut scheduler, clock) = scheduler_at(at(9, 0), &Config::new());
+        scheduler.record_prompt();
+        clock.set(at(10, 0));
+        scheduler.apply(ReminderAction::Snooze(Duration::minutes(15)));
+        assert_eq!(scheduler.next_prompt(true), (at(10, 15), Prompt::CheckIn));
+
+        clock.set(at(10, 15));
+        scheduler.apply(ReminderAction::Skip);
+        assert_eq!(scheduler.next_prompt(true), (at(11, 15), Prompt::CheckIn));
+
+        // A snooze is a week at most
+        scheduler.apply(ReminderAction::Snooze(Duration::MAX));
+        assert_eq!(
+            scheduler.snoozed_until,
+            Some(at(10, 15) + Duration::minutes(MAX_SNOOZE_MINUTES))
+        );
+    }
+
+    #[test]
+    fn skipping_ahead_passes_over_the_coming_slot() {
+        let mut config = Config::new();
+        config.schedule.fixed_times = [10, 14, 18]
+            .into_iter()
+            .map(|hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap())
+            .collect();
+        let (mut scheduler, clock) = scheduler_at(at(9, 50), &config);
+        assert_eq!(scheduler.next_prompt(true), (at(10, 0), Prompt::CheckIn));
+        scheduler.apply(ReminderAction::Skip);
+        assert_eq!(scheduler.next_prompt(true), (at(14, 0), Prompt::CheckIn));
+
+        // Skipping the prompt that's up now doesn't touch the slot after it
+        clock.set(at(14, 0));
+        scheduler.apply(ReminderAction::Skip);
+        assert_eq!(scheduler.next_prompt(true), (at(18, 0), Prompt::CheckIn));
+    }
+}
@@ -0,0 +1,140 @@
+// search.rs
+use chrono::NaiveDate;
+use diary_app::{Config, StorageType};
+use std::error::Error;
+
+use crate::config::ConfigManager;
+use crate::storage::sqlite::SqliteStorage;
+
+pub struct SearchOptions {
+    pub query: String,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub limit: usize,
+}
+
+/// Prints the sections matching `options.query`, searched through the SQLite index.
+pub fn run_search(config: &Config, options: &SearchOptions) -> Result<(), Box<dyn Error>> {
+    if !config.sqlite_index && !matches!(config.storage_type, StorageType::Sqlite) {
+        return Err("Search needs the SQLite index, enable it with --config".into());
+    }
+
+    let fts_query = to_fts_query(&options.query)?;
+    let index = SqliteStorage::new(ConfigManager::data_dir().join("diary.sqlite3"))?;
+    let hits = index
+        .search(&fts_query, options.from, options.to, options.limit)
+        .map_err(|err| format!("Invalid search query `{}`: {}", options.query, err))?;
+
+    if hits.is_empty() {
+        println!("No entries found for `{}`", options.query);
+        return Ok(());
+    }
+    for hit in hits {
+        match hit.time {
+            Some(time) => println!("{} ## {}", hit.date, time.format("%H:%M")),
+            None => println!("{}", hit.date),
+        }
+        for line in hit.snippet.lines().filter(|l| !l.trim().is_empty()) {
+            println!("    {}", line);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Turns a user query into an FTS5 expression.
+///
+/// `"quoted phrases"`, `AND`/`OR`/`NOT`, parentheses and trailing `*` prefixes keep their
+/// meaning, `-word` means `NOT word`, and every other word is quoted so punctuation in it
+/// can't break the query syntax. FTS5's `NOT` needs a term before it, so a query can't
+/// start with `-word`.
+pub fn to_fts_query(query: &str) -> Result<String, String> {
+    let mut terms: Vec<String> = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            terms.push(quote(&phrase));
+        } else if c == '(' || c == ')' {
+            chars.next();
+            terms.push(c.to_string());
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' || c == '(' || c == ')' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            match word.as_str() {
+                "AND" | "OR" | "NOT" => terms.push(word),
+                _ => {
+                    let (negated, word) = match word.strip_prefix('-') {
+                        Some(rest) if !rest.is_empty() => (true, rest),
+                        _ => (false, word.as_str()),
+                    };
+                    let term = match word.strip_suffix('*') {
+                        Some(prefix) if !prefix.is_empty() => format!("{}*", quote(prefix)),
+                        _ => quote(word),
+                    };
+                    if negated {
+                        match terms.last().map(String::as_str) {
+                            // `a AND -b` is `a NOT b`
+                            Some("AND") => {
+                                terms.pop();
+                            }
+                            None | Some("(" | "OR" | "NOT") => {
+                                return Err(format!(
+                                    "`-{}` leaves out matches of something else, search for that first, e.g. `diary -{}`",
+                                    word, word
+                                ));
+                            }
+                            Some(_) => {}
+                        }
+                        terms.push("NOT".to_string());
+                    }
+                    terms.push(term);
+                }
+            }
+        }
+    }
+    Ok(terms.join(" "))
+}
+
+fn quote(term: &str) -> String {
+    format!("\"{}\"", term.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_words_and_keeps_operators() {
+        assert_eq!(
+            to_fts_query(r#"deploy* "on call" OR (outage AND db-1)"#).unwrap(),
+            r#""deploy"* "on call" OR ( "outage" AND "db-1" )"#
+        );
+        assert_eq!(to_fts_query(r#"say "hi""#).unwrap(), r#""say" "hi""#);
+    }
+
+    #[test]
+    fn negates_with_a_term_before_it() {
+        assert_eq!(
+            to_fts_query("deploy -work").unwrap(),
+            r#""deploy" NOT "work""#
+        );
+        assert_eq!(
+            to_fts_query("deploy AND -work").unwrap(),
+            r#""deploy" NOT "work""#
+        );
+        assert_eq!(to_fts_query("a - b").unwrap(), r#""a" "-" "b""#);
+        assert!(to_fts_query("-work").is_err());
+        assert!(to_fts_query("deploy OR -work").is_err());
+        assert!(to_fts_query("(-work)").is_err());
+    }
+}
@@ -1,14 +1,14 @@
 // setup.rs
-use dialoguer::{Input, Select};
-use std::borrow::Borrow;
+use dialoguer::{Confirm, Input, Select};
 use std::error::Error;
 // use std::fs;
 // use std::path::PathBuf;
 use chrono::NaiveTime;
 use diary_app::Config;
 
-use diary_app::StorageType;
+use diary_app::{EditorConfig, PromptMode, StorageType};
 
+use super::editor::detect_terminals;
 use super::iplocation::ipapi::get_ip_location;
 // use diary_app::iplocation::ipapi::get_api_location;
 
@@ -35,13 +35,22 @@ impl SetupWizard {
                 "Editor frequency: {} minutes",
                 current.editor_frequency_minutes
             );
+            println!("Prompt: {:?}", current.prompt_mode);
+            println!(
+                "Editor: {}",
+                if current.editor.builtin {
+                    "built-in capture window"
+                } else {
+                    current.editor.command.as_deref().unwrap_or("$VISUAL / $EDITOR")
+                }
+            );
             println!("\nPress Enter to keep current value, or input a new value.");
         }
 
         config.storage_type = Self::prompt_storage_type(current_config.map(|c| &c.storage_type))?;
         let ip_location = get_ip_location().await?;
         let city_default_setting = current_config
-            .filter(|c| c.city.len() > 0)
+            .filter(|c| !c.city.is_empty())
             .map_or(&ip_location, |c| &c.city);
         config.city = Self::prompt_string("City", Some(city_default_setting))?;
         config.day_start_time = Self::prompt_time(
@@ -52,6 +61,8 @@ impl SetupWizard {
             "Editor frequency (minutes)",
             current_config.map(|c| &c.editor_frequency_minutes),
         )?;
+        config.prompt_mode = Self::prompt_prompt_mode(config.prompt_mode)?;
+        config.editor = Self::prompt_editor(&config.editor)?;
 
         match config.storage_type {
             StorageType::Notion => {
@@ -64,6 +75,12 @@ impl SetupWizard {
                     current_config.and_then(|c| c.notion_database_id.as_ref()),
                 )?);
             }
+            StorageType::Vault => {
+                config.vault_path = Some(Self::prompt_string(
+                    "Obsidian / Logseq vault path",
+                    current_config.and_then(|c| c.vault_path.as_ref()),
+                )?);
+            }
 
             /*StorageType::GoogleDrive => {
                 println!("To set up Google Drive, please follow these steps:");
@@ -76,18 +93,30 @@ impl SetupWizard {
             StorageType::Local => {
                 // No additional setup needed for local storage
             }
+            StorageType::Sqlite => {
+                // The database lives in the app data directory
+            }
+        }
+
+        if !matches!(config.storage_type, StorageType::Sqlite) {
+            config.sqlite_index = Self::prompt_bool(
+                "Keep a local SQLite index for search and offline reading?",
+                config.sqlite_index,
+            )?;
         }
 
         Ok(config)
     }
 
     fn prompt_storage_type(current: Option<&StorageType>) -> Result<StorageType, Box<dyn Error>> {
-        let options = vec!["Local", "Notion", "Google Drive"];
+        let options = vec!["Local", "Notion", "Obsidian / Logseq vault", "SQLite"];
         let default = current
             .map(|s| match s {
                 StorageType::Local => 0,
                 StorageType::Notion => 1,
-                // StorageType::GoogleDrive => 2,
+                StorageType::Vault => 2,
+                StorageType::Sqlite => 3,
+                // StorageType::GoogleDrive => 4,
             })
             .unwrap_or(0);
 
@@ -100,11 +129,91 @@ impl SetupWizard {
         Ok(match selected {
             0 => StorageType::Local,
             1 => StorageType::Notion,
-            // 2 => StorageType::GoogleDrive,
+            2 => StorageType::Vault,
+            3 => StorageType::Sqlite,
+            // 4 => StorageType::GoogleDrive,
+            _ => unreachable!(),
+        })
+    }
+
+    fn prompt_prompt_mode(current: PromptMode) -> Result<PromptMode, Box<dyn Error>> {
+        let options = vec![
+            "Open the editor right away",
+            "Ask with a desktop notification first",
+        ];
+        let default = match current {
+            PromptMode::Editor => 0,
+            PromptMode::Notification => 1,
+        };
+
+        let selected = Select::new()
+            .with_prompt("When it's time to write")
+            .items(&options)
+            .default(default)
+            .interact()?;
+
+        Ok(match selected {
+            0 => PromptMode::Editor,
+            1 => PromptMode::Notification,
             _ => unreachable!(),
         })
     }
 
+    fn prompt_editor(current: &EditorConfig) -> Result<EditorConfig, Box<dyn Error>> {
+        let mut editor = current.clone();
+        editor.builtin = Self::prompt_bool(
+            "Write in the built-in capture window instead of an external editor?",
+            current.builtin,
+        )?;
+        if editor.builtin {
+            return Ok(editor);
+        }
+        let command = Self::prompt_string(
+            "Editor command (leave empty for $VISUAL / $EDITOR)",
+            current.command.as_ref(),
+        )?;
+        editor.command = Some(command).filter(|command| !command.trim().is_empty());
+        editor.gui = Self::prompt_bool(
+            "Does the editor open its own window, like `code --wait` or `gvim -f`?",
+            current.gui,
+        )?;
+        if !editor.gui {
+            editor.terminal = Self::prompt_terminal(current.terminal.as_ref())?;
+        }
+        Ok(editor)
+    }
+
+    fn prompt_terminal(current: Option<&String>) -> Result<Option<String>, Box<dyn Error>> {
+        let detected = detect_terminals();
+        let mut options = vec!["Pick an installed terminal automatically".to_string()];
+        options.extend(detected.iter().cloned());
+        options.push("Other".to_string());
+
+        let default = match current {
+            None => 0,
+            Some(current) => detected
+                .iter()
+                .position(|template| template == current)
+                .map_or(options.len() - 1, |index| index + 1),
+        };
+        let selected = Select::new()
+            .with_prompt("Terminal to run the editor in")
+            .items(&options)
+            .default(default)
+            .interact()?;
+
+        Ok(if selected == 0 {
+            None
+        } else if selected <= detected.len() {
+            Some(detected[selected - 1].clone())
+        } else {
+            Some(Self::prompt_string(
+                "Terminal command, {editor} stands for the editor",
+                current,
+            )?)
+        })
+    }
+
     fn prompt_string(prompt: &str, current: Option<&String>) -> Result<String, Box<dyn Error>> {
         let input: String = Input::new()
             .with_prompt(prompt)
@@ -112,13 +221,19 @@ impl SetupWizard {
             .allow_empty(true)
             .interact_text()?;
 
-        Ok(if input.is_empty() && current.is_some() {
-            current.unwrap().clone()
-        } else {
-            input
+        Ok(match current {
+            Some(current) if input.is_empty() => current.clone(),
+            _ => input,
         })
     }
 
+    fn prompt_bool(prompt: &str, current: bool) -> Result<bool, Box<dyn Error>> {
+        Ok(Confirm::new()
+            .with_prompt(prompt)
+            .default(current)
+            .interact()?)
+    }
+
     fn prompt_time(prompt: &str, current: Option<&NaiveTime>) -> Result<NaiveTime, Box<dyn Error>> {
         loop {
             let input: String = Input::new()
@@ -131,8 +246,8 @@ impl SetupWizard {
                 .allow_empty(true)
                 .interact_text()?;
 
-            if input.is_empty() && current.is_some() {
-                return Ok(current.unwrap().clone());
+            if let Some(current) = current.filter(|_| input.is_empty()) {
+                return Ok(*current);
             }
 
             if let Ok(time) = NaiveTime::parse_from_str(&input, "%H:%M") {
@@ -151,8 +266,8 @@ impl SetupWizard {
                 .allow_empty(true)
                 .interact_text()?;
 
-            if input.is_empty() && current.is_some() {
-                return Ok(*current.unwrap());
+            if let Some(current) = current.filter(|_| input.is_empty()) {
+                return Ok(*current);
             }
 
             if let Ok(number) = input.parse::<u32>() {
@@ -169,9 +284,20 @@ pub async fn add_auto_start_entry() -> Result<(), Box<dyn std::error::Error>> {
         let autostart_dir = base_dirs.config_dir().join("autostart");
         std::fs::create_dir_all(&autostart_dir)?;
 
-        let desktop_path = autostart_dir.join("com.ravinderpayal.DiaryApp.desktop");
-        if !desktop_path.exists() {
-            let bin_path: String = env::current_exe()?.to_str().unwrap().to_string();
+        let bin_path: String = env::current_exe()?.to_str().unwrap().to_string();
+        // The app itself, and the client that opens the editor for it in the session
+        for (file_name, name, exec) in [
+            ("com.ravinderpayal.DiaryApp.desktop", "Diary App", bin_path.clone()),
+            (
+                "com.ravinderpayal.DiaryApp.Client.desktop",
+                "Diary App Client",
+                format!("{} client", bin_path),
+            ),
+        ] {
+            let desktop_path = autostart_dir.join(file_name);
+            if desktop_path.exists() {
+                continue;
+            }
             let deskop_entry = format!(
                 r#"[Desktop Entry]
 Type=Application
@@ -179,10 +305,10 @@ Exec={}
 Hidden=false
 NoDisplay=false
 X-GNOME-Autostart-enabled=true
-Name=Diary App
+Name={}
 Comment[en_IN]=
 Comment="#,
-                bin_path
+                exec, name
             );
             // Write the service file
             fs::write("/tmp/da-desktop.entry", deskop_entry).await?;
@@ -190,7 +316,7 @@ Comment="#,
             // Move the service file to the correct location and set permissions
             println!("Adding Desktop Entry at {}", desktop_path.to_str().unwrap());
             Command::new("sudo")
-                .args(&[
+                .args([
                     "mv",
                     "/tmp/da-desktop.entry",
                     desktop_path
@@ -202,7 +328,7 @@ Comment="#,
                 .await?;
 
             Command::new("sudo")
-                .args(&["chmod", "644", desktop_path.to_str().unwrap()])
+                .args(["chmod", "644", desktop_path.to_str().unwrap()])
                 .status()
                 .await?;
             println!("Diary service has been set up and started.");
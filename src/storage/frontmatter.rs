@@ -0,0 +1,192 @@
+// storage/frontmatter.rs
+use chrono::{Local, NaiveDate};
+use serde_yaml::{Mapping, Value};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use diary_app::{Entry, EntryMetadata};
+
+/// The frontmatter keys [`EntryMetadata`] is written to.
+const METADATA_KEYS: &[&str] = &[
+    "location", "weather", "tags", "mood", "energy", "sections", "created", "updated",
+];
+/// The location again under the key vault notes had it as first, which vault queries
+/// may still use.
+const CITY_KEY: &str = "city";
+
+/// Splits a note into its YAML frontmatter and the body. A block between `---` lines
+/// is only frontmatter when it reads as a YAML mapping, a body may well start with a
+/// `---` rule.
+pub fn split_frontmatter(note: &str) -> (Option<Mapping>, &str) {
+    let Some(rest) = note
+        .strip_prefix("---\n")
+        .or_else(|| note.strip_prefix("---\r\n"))
+    else {
+        return (None, note);
+    };
+    let mut end = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end_matches(['\r', '\n']) == "---" {
+            let yaml = &rest[..end];
+            return match serde_yaml::from_str::<Mapping>(yaml) {
+                Ok(frontmatter) if !yaml.trim().is_empty() => {
+                    (Some(frontmatter), &rest[end + line.len()..])
+                }
+                _ => (None, note),
+            };
+        }
+        end += line.len();
+    }
+    (None, note)
+}
+
+/// The frontmatter of the note at `path`, so fields added by hand survive a save.
+pub fn read_frontmatter(path: &Path) -> Mapping {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|note| split_frontmatter(&note).0)
+        .unwrap_or_default()
+}
+
+/// Puts the frontmatter back on top of `body`, none when it's empty.
+pub fn join_frontmatter(frontmatter: &Mapping, body: &str) -> Result<String, serde_yaml::Error> {
+    if frontmatter.is_empty() {
+        return Ok(body.to_string());
+    }
+    Ok(format!(
+        "---\n{}---\n{}",
+        serde_yaml::to_string(frontmatter)?,
+        body
+    ))
+}
+
+/// Writes the metadata fields into `frontmatter`, taking out the ones it hasn't got.
+/// The location goes in `city` as well. Other keys are left as they are.
+pub fn write_metadata(
+    frontmatter: &mut Mapping,
+    metadata: &EntryMetadata,
+) -> Result<(), serde_yaml::Error> {
+    for key in METADATA_KEYS {
+        frontmatter.remove(*key);
+    }
+    if let Value::Mapping(fields) = serde_yaml::to_value(metadata)? {
+        frontmatter.extend(fields);
+    }
+    if let Some(location) = &metadata.location {
+        frontmatter.insert(CITY_KEY.into(), location.as_str().into());
+    }
+    Ok(())
+}
+
+/// The metadata fields in `frontmatter`, `None` when they don't read as metadata.
+pub fn read_metadata(frontmatter: &Mapping) -> Option<EntryMetadata> {
+    let mut metadata: EntryMetadata =
+        serde_yaml::from_value(Value::Mapping(frontmatter.clone())).ok()?;
+    if metadata.location.is_none() {
+        metadata.location = frontmatter
+            .get(CITY_KEY)
+            .and_then(Value::as_str)
+            .map(str::to_string);
+    }
+    Some(metadata)
+}
+
+/// The entry in the note at `path` with the metadata from its frontmatter. Notes from
+/// before there was any have it worked out from the body.
+pub fn read_note(path: &Path, date: NaiveDate) -> Result<Option<Entry>, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let note = fs::read_to_string(path)?;
+    let (frontmatter, body) = split_frontmatter(&note);
+    let metadata = frontmatter
+        .and_then(|frontmatter| read_metadata(&frontmatter))
+        .unwrap_or_else(|| EntryMetadata::from_body(body));
+    Ok(Some(Entry {
+        date,
+        body: body.to_string(),
+        metadata,
+    }))
+}
+
+/// The entry for saving `content` over the note at `path`, keeping the metadata that
+/// doesn't come from the body.
+pub fn updated_entry(path: &Path, date: NaiveDate, content: &str) -> Result<Entry, Box<dyn Error>> {
+    let body = split_frontmatter(content).1;
+    let (previous, mut metadata) = match read_note(path, date)? {
+        Some(entry) => (entry.body, entry.metadata),
+        None => (String::new(), EntryMetadata::default()),
+    };
+    metadata.update_from_body(&previous, body);
+    Ok(Entry {
+        date,
+        body: body.to_string(),
+        metadata,
+    })
+}
+
+/// Writes `entry` to `path` with its metadata as frontmatter, next to `extra` fields and
+/// anything else put there by hand.
+pub fn write_note(path: &Path, entry: &Entry, extra: Mapping) -> Result<(), Box<dyn Error>> {
+    let mut frontmatter = read_frontmatter(path);
+    let now = Local::now();
+    let mut metadata = entry.metadata.clone();
+    metadata.created = metadata
+        .created
+        .or_else(|| read_metadata(&frontmatter).and_then(|existing| existing.created))
+        .or(Some(now));
+    metadata.updated = Some(now);
+    frontmatter.extend(extra);
+    write_metadata(&mut frontmatter, &metadata)?;
+    fs::write(path, join_frontmatter(&frontmatter, &entry.body)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_location_as_city_too() {
+        let mut frontmatter: Mapping =
+            serde_yaml::from_str("city: Delhi\naliases: [today]").unwrap();
+        let metadata = EntryMetadata {
+            location: Some("Pune".to_string()),
+            ..EntryMetadata::default()
+        };
+        write_metadata(&mut frontmatter, &metadata).unwrap();
+        assert_eq!(frontmatter.get("location"), Some(&Value::from("Pune")));
+        assert_eq!(frontmatter.get("city"), Some(&Value::from("Pune")));
+        assert!(frontmatter.contains_key("aliases"));
+
+        let only_city: Mapping = serde_yaml::from_str("city: Delhi").unwrap();
+        let metadata = read_metadata(&only_city).unwrap();
+        assert_eq!(metadata.location.as_deref(), Some("Delhi"));
+    }
+
+    #[test]
+    fn splits_only_yaml_frontmatter() {
+        let (frontmatter, body) = split_frontmatter("---\nmood: 3\n---\n# Day\n");
+        assert_eq!(frontmatter.unwrap().get("mood"), Some(&Value::from(3)));
+        assert_eq!(body, "# Day\n");
+
+        let (frontmatter, body) = split_frontmatter("---\r\nmood: 3\r\n---\r\nDay\r\n");
+        assert!(frontmatter.is_some());
+        assert_eq!(body, "Day\r\n");
+
+        let (frontmatter, body) = split_frontmatter("---\ntags: [a]\n---");
+        assert!(frontmatter.is_some());
+        assert_eq!(body, "");
+
+        // Rules in the body aren't frontmatter
+        for note in [
+            "---\nMorning walk\n---\nLunch\n",
+            "---\n---\nLunch\n",
+            "---\nbroken: [\n---\n",
+            "----\nmood: 3\n---\n",
+        ] {
+            assert_eq!(split_frontmatter(note), (None, note));
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// storage/history.rs
+use async_trait::async_trait;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use diary_app::{Entry, HistoryConfig, Storage};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+const REVISION_FORMAT: &str = "%Y%m%dT%H%M%S%.3f";
+
+/// A previous version of an entry, `number` 1 being the most recent one.
+pub struct Revision {
+    pub number: usize,
+    pub saved_at: NaiveDateTime,
+    pub path: PathBuf,
+}
+
+impl Revision {
+    pub fn content(&self) -> Result<String, Box<dyn Error>> {
+        Ok(fs::read_to_string(&self.path)?)
+    }
+}
+
+/// Local store of overwritten entries, one folder per day and one file per revision.
+pub struct History {
+    dir: PathBuf,
+    config: HistoryConfig,
+}
+
+impl History {
+    pub fn new(dir: PathBuf, config: HistoryConfig) -> Self {
+        History { dir, config }
+    }
+
+    /// Revisions of `date`, newest first.
+    pub fn revisions(&self, date: NaiveDate) -> Result<Vec<Revision>, Box<dyn Error>> {
+        let day_dir = self.dir.join(date.format("%Y-%m-%d").to_string());
+        if !day_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut revisions: Vec<(NaiveDateTime, PathBuf)> = fs::read_dir(day_dir)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let path = entry.path();
+                let stem = path.file_stem()?.to_str()?.to_string();
+                NaiveDateTime::parse_from_str(&stem, REVISION_FORMAT)
+                    .ok()
+                    .map(|saved_at| (saved_at, path))
+            })
+            .collect();
+        revisions.sort_by_key(|(saved_at, _)| std::cmp::Reverse(*saved_at));
+
+        Ok(revisions
+            .into_iter()
+            .enumerate()
+            .map(|(i, (saved_at, path))| Revision {
+                number: i + 1,
+                saved_at,
+                path,
+            })
+            .collect())
+    }
+
+    pub fn revision(&self, date: NaiveDate, number: usize) -> Result<Revision, Box<dyn Error>> {
+        self.revisions(date)?
+            .into_iter()
+            .find(|r| r.number == number)
+            .ok_or_else(|| format!("No revision {} for {}", number, date).into())
+    }
+
+    fn record(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
+        let day_dir = self.dir.join(date.format("%Y-%m-%d").to_string());
+        fs::create_dir_all(&day_dir)?;
+        let saved_at = Local::now().naive_local();
+        fs::write(
+            day_dir.join(format!("{}.md", saved_at.format(REVISION_FORMAT))),
+            content,
+        )?;
+        self.prune_all()
+    }
+
+    /// Prunes every day, so old revisions go even for days that aren't saved again.
+    fn prune_all(&self) -> Result<(), Box<dyn Error>> {
+        for day_dir in fs::read_dir(&self.dir)?.filter_map(Result::ok) {
+            let date = day_dir
+                .file_name()
+                .to_str()
+                .and_then(|name| NaiveDate::parse_from_str(name, "%Y-%m-%d").ok());
+            if let Some(date) = date {
+                self.prune(date)?;
+                // Only goes when nothing is left in it
+                let _ = fs::remove_dir(day_dir.path());
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops revisions beyond `max_revisions` or older than `max_age_days`, 0 disables either cap.
+    fn prune(&self, date: NaiveDate) -> Result<(), Box<dyn Error>> {
+        let oldest_kept =
+            Local::now().naive_local() - Duration::days(self.config.max_age_days.into());
+        for revision in self.revisions(date)? {
+            let too_many =
+                self.config.max_revisions > 0 && revision.number > self.config.max_revisions;
+            let too_old = self.config.max_age_days > 0 && revision.saved_at < oldest_kept;
+            if too_many || too_old {
+                fs::remove_file(revision.path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps a storage so every save first keeps the content it is about to replace.
+pub struct HistoryStorage {
+    history: History,
+    backend: Box<dyn Storage>,
+}
+
+impl HistoryStorage {
+    pub fn new(history: History, backend: Box<dyn Storage>) -> Self {
+        HistoryStorage { history, backend }
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for HistoryStorage {
+    async fn save_entry(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(previous) = self.backend.get_entry(date).await? {
+            if previous != content {
+                self.history.record(date, &previous)?;
+            }
+        }
+        self.backend.save_entry(date, content).await
+    }
+
+    async fn get_entry(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>> {
+        self.backend.get_entry(date).await
+    }
+
+    async fn get_latest_entry(&self) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>> {
+        self.backend.get_latest_entry().await
+    }
+
+    async fn list_entries(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        self.backend.list_entries().await
+    }
+
+    async fn get_entry_with_metadata(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<Entry>, Box<dyn Error>> {
+        self.backend.get_entry_with_metadata(date).await
+    }
+
+    async fn save_entry_with_metadata(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        if let Some(previous) = self.backend.get_entry(entry.date).await? {
+            if previous != entry.body {
+                self.history.record(entry.date, &previous)?;
+            }
+        }
+        self.backend.save_entry_with_metadata(entry).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prunes_days_that_are_not_saved_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(
+            dir.path().to_path_buf(),
+            HistoryConfig {
+                max_revisions: 2,
+                max_age_days: 30,
+            },
+        );
+        let day = |d| NaiveDate::from_ymd_opt(2024, 3, d).unwrap();
+        let old_day = dir.path().join("2024-03-01");
+        fs::create_dir_all(&old_day).unwrap();
+        let long_ago = Local::now().naive_local() - Duration::days(40);
+        fs::write(
+            old_day.join(format!("{}.md", long_ago.format(REVISION_FORMAT))),
+            "old",
+        )
+        .unwrap();
+
+        for content in ["one", "two", "three"] {
+            history.record(day(5), content).unwrap();
+            // Revisions are named after the millisecond they were saved in
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        assert!(!old_day.exists());
+        let kept: Vec<String> = history
+            .revisions(day(5))
+            .unwrap()
+            .iter()
+            .map(|revision| revision.content().unwrap())
+            .collect();
+        assert_eq!(kept, ["three", "two"]);
+    }
+}
@@ -1,13 +1,13 @@
 // storage/local.rs
 use async_trait::async_trait;
 use chrono::NaiveDate;
+use diary_app::{Entry, Storage};
+use serde_yaml::Mapping;
 use std::error::Error;
-use std::fs::{self, File};
-use std::io::Read;
+use std::fs;
 use std::path::PathBuf;
-use diary_app::Storage;
-
 
+use crate::storage::frontmatter::{read_note, updated_entry, write_note};
 
 pub struct LocalStorage {
     base_path: PathBuf,
@@ -17,47 +17,60 @@ impl LocalStorage {
     pub fn new(base_path: PathBuf) -> Self {
         LocalStorage { base_path }
     }
+
+    fn entry_path(&self, date: NaiveDate) -> PathBuf {
+        self.base_path.join(format!("{}.md", date))
+    }
 }
 
 #[async_trait(?Send)]
 impl Storage for LocalStorage {
     async fn save_entry(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
-        let file_path = self.base_path.join(format!("{}.md", date));
-        fs::write(file_path, content)?;
-        Ok(())
+        let entry = updated_entry(&self.entry_path(date), date, content)?;
+        self.save_entry_with_metadata(&entry).await
     }
 
     async fn get_entry(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>> {
-        let file_path = self.base_path.join(format!("{}.md", date));
-        if file_path.exists() {
-            let mut content = String::new();
-            File::open(file_path)?.read_to_string(&mut content)?;
-            Ok(Some(content))
-        } else {
-            Ok(None)
-        }
+        Ok(read_note(&self.entry_path(date), date)?.map(|entry| entry.body))
     }
 
     async fn get_latest_entry(&self) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>> {
-        let mut entries: Vec<_> = fs::read_dir(&self.base_path)?
+        let latest = self.list_entries().await?.pop();
+        match latest {
+            Some(date) => {
+                Ok(read_note(&self.entry_path(date), date)?.map(|entry| (date, entry.body)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn list_entries(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        if !self.base_path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut dates: Vec<NaiveDate> = fs::read_dir(&self.base_path)?
             .filter_map(Result::ok)
-            .filter(|entry| {
-                entry.path().extension().map(|ext| ext == "md").unwrap_or(false)
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != "md" {
+                    return None;
+                }
+                NaiveDate::parse_from_str(path.file_stem()?.to_str()?, "%Y-%m-%d").ok()
             })
             .collect();
+        dates.sort();
+        Ok(dates)
+    }
 
-        entries.sort_by_key(|entry| entry.path());
-
-        if let Some(latest) = entries.last() {
-            let date = NaiveDate::parse_from_str(
-                latest.path().file_stem().unwrap().to_str().unwrap(),
-                "%Y-%m-%d",
-            )?;
-            let mut content = String::new();
-            File::open(latest.path())?.read_to_string(&mut content)?;
-            Ok(Some((date, content)))
-        } else {
-            Ok(None)
-        }
+    async fn get_entry_with_metadata(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<Entry>, Box<dyn Error>> {
+        read_note(&self.entry_path(date), date)
+    }
+
+    /// Writes the metadata as YAML frontmatter, next to anything else put there by hand.
+    async fn save_entry_with_metadata(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        write_note(&self.entry_path(entry.date), entry, Mapping::new())
     }
 }
@@ -1,4 +1,8 @@
 // storage/mod.rs
+pub mod frontmatter;
+pub mod history;
 pub mod local;
 pub mod notion;
 pub mod notion_md_interop;
+pub mod sqlite;
+pub mod vault;
@@ -2,12 +2,11 @@
 // use crate::Storage;
 use async_trait::async_trait;
 use chrono::NaiveDate;
-use ids::{AsIdentifier, BlockId};
+use ids::BlockId;
 use notion::models::block::Block;
 use notion::*;
 use reqwest::header::{HeaderMap, HeaderValue};
 use reqwest::{header, Client, ClientBuilder, RequestBuilder};
-use serde::Serialize;
 use serde_json::json;
 use std::error::Error;
 use std::fmt::Debug;
@@ -15,7 +14,7 @@ use std::str::FromStr;
 use std::sync::Arc;
 use tracing::Instrument;
 
-use diary_app::Storage;
+use diary_app::{Entry, EntryMetadata, Storage};
 use std::collections::HashMap;
 
 use crate::storage::notion_md_interop::{MarkdownToNotionBlocks, ToMarkdown};
@@ -28,7 +27,7 @@ pub struct NotionStorage {
 
 impl NotionStorage {
     pub fn new(token: String, database_id: String) -> Self {
-        let d_id = ids::DatabaseId::from_str(format!("{}", &database_id).as_ref())
+        let d_id = ids::DatabaseId::from_str(&database_id)
             .expect("Valid Database Id is required");
         let notion_api = Arc::new(NotionApi::new(token.clone()).expect("Notion is setup"));
         NotionStorage {
@@ -61,36 +60,51 @@ impl NotionStorage {
             )
             .await?;
         // todo : implement filter
-        Ok(blocks.results.into_iter().map(|b| b.clone()).collect())
+        Ok(blocks.results.into_iter().collect())
     }
-    async fn find_latest_page(&self) -> Result<Option<ids::PageId>, Box<dyn Error>> {
-        println!("Finding Page in the database:{}", &self.database_id);
 
-        let pages = self
-            .client
-            .query_database(
-                &self.database_id,
-                models::search::DatabaseQuery {
-                    filter: None,
-                    sorts: Some(vec![models::search::DatabaseSort {
-                        property: None,
-                        direction: notion::models::search::SortDirection::Descending,
-                        timestamp: Some(notion::models::search::DatabaseSortTimestamp::CreatedTime),
-                    }]),
-                    paging: None,
-                },
-            )
-            .await?;
-        println!("pages fetched: {}", pages.results.len());
-        // todo : implement filter
-        Ok(pages.results.get(0).map(|page| page.id.clone()))
+    /// Dates of all pages in the database, pages are titled with their date.
+    async fn find_page_dates(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        let mut dates = Vec::new();
+        let mut start_cursor = None;
+        loop {
+            let pages = self
+                .client
+                .query_database(
+                    &self.database_id,
+                    models::search::DatabaseQuery {
+                        filter: None,
+                        sorts: None,
+                        paging: Some(models::paging::Paging {
+                            start_cursor,
+                            page_size: Some(100),
+                        }),
+                    },
+                )
+                .await?;
+            dates.extend(pages.results.iter().filter_map(|page| {
+                NaiveDate::parse_from_str(page.properties.title()?.trim(), "%Y-%m-%d").ok()
+            }));
+            if !pages.has_more || pages.next_cursor.is_none() {
+                break;
+            }
+            start_cursor = pages.next_cursor;
+        }
+        dates.sort();
+        dates.dedup();
+        Ok(dates)
     }
 
     async fn find_page_for_date(
         &self,
         date: NaiveDate,
     ) -> Result<Option<ids::PageId>, Box<dyn Error>> {
-        let filter = json!({
+        Ok(self.find_page(date).await?.map(|page| page.id))
+    }
+
+    /// The page titled with `date`, with its properties.
+    async fn find_page(&self, date: NaiveDate) -> Result<Option<models::Page>, Box<dyn Error>> {
+        let _filter = json!({
             "property": "Date",
             "date": {
                 "equals": date.format("%Y-%m-%d").to_string()
@@ -119,16 +133,28 @@ impl NotionStorage {
             .await?;
         println!("pages fetched: {}", pages.results.len());
         // todo : implement filter
-        Ok(pages.results.get(0).map(|page| page.id.clone()))
+        Ok(pages.results.into_iter().next())
+    }
+
+    /// The database's properties by name, empty when it can't be fetched.
+    async fn schema(&self) -> HashMap<String, models::properties::PropertyConfiguration> {
+        match self.client.get_database(&self.database_id).await {
+            Ok(database) => database.properties,
+            Err(err) => {
+                eprintln!("Couldn't read the database properties, saving without metadata: {}", err);
+                HashMap::new()
+            }
+        }
     }
  
-    async fn create_new_page(&self, content: &str, date: NaiveDate)  -> Result<(), Box<dyn Error>> {
+    async fn create_new_page(&self, entry: &Entry)  -> Result<ids::PageId, Box<dyn Error>> {
+            let (content, date) = (entry.body.as_str(), entry.date);
             // Update existing page
             // let blocks = notion_to_blocks::string_to_blocks(content);
             // self.client.update_block_children(&page_id, blocks).await?;
 
             let title = date.format("%Y-%m-%d").to_string();
-            let properties = HashMap::from([(
+            let mut properties = HashMap::from([(
                 "Name".to_string(),
                 models::properties::PropertyValue::Title {
                     id: ids::PropertyId::from_str("title-asdadadadada")
@@ -147,6 +173,9 @@ impl NotionStorage {
                 },
             )]);
 
+            // Only the properties the database has, with the types they're written as
+            properties.extend(metadata_properties(&entry.metadata, &self.schema().await));
+
             let page = models::PageCreateRequest {
                 parent: models::Parent::Database {
                     database_id: self.database_id.clone()
@@ -157,18 +186,10 @@ impl NotionStorage {
                 ),
             };
 
-            let page_req = async move { self.client.create_page(page).await };
-
-            let page_req_response = page_req.await;
-            match page_req_response {
-                Ok(_) => println!("Synced with notion"),
-                Err(err) => {
-                    println!("Sync failed {}", err);
-                },
-            };
-            // todo: Implement backup and sync if notion call fails
- 
-        return Ok(());
+            // A failure goes back to the caller, the SQLite index keeps the entry until `sync`
+            let page = self.client.create_page(page).await?;
+            println!("Synced with notion");
+            Ok(page.id)
     }
 
 }
@@ -176,94 +197,176 @@ impl NotionStorage {
 #[async_trait(?Send)]
 impl Storage for NotionStorage {
     async fn save_entry(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
-        if let Some(page_id) = self.find_page_for_date(date.clone()).await? {
-            // let client = std::sync::Arc::clone(&self.client);
-            println!("Updating existing page: {}", page_id.to_string());
-            if let Ok(blocks) = self.get_blocks_in_a_page(&page_id).await {
-                let block_ids: Vec<ids::BlockId> = blocks
-                    .iter()
-                    .map(get_block_id)
-                    .filter(|opt| opt.is_some())
-                    .map(|opt| opt.unwrap())
-                    .collect();
-
-                println!("Deleting Existing Blocks(Count: {}) using page archive method in single hit", block_ids.len());
-                // delete_page
-                match delete_blocks(&self.api_token, vec![BlockId::from_str(page_id.to_string().as_ref()).unwrap()]).await {
-                    Err(er) => eprintln!(
-                        "Delete Process failed somehow {}: {}",
-                        &page_id.as_id(),
-                        &er
-                    ),
-                    Ok(_) => (),
-                };
-                println!("Inserting fresh content");
-                /*
-                match insert_blocks_in_page(&self.api_token, &page_id, content.to_notion_blocks())
-                    .await
-                {
-                    Err(er) => eprintln!("Insert failed somehow {}: {}", &page_id.as_id(), &er),
-                    Ok(_) => println!("Saved Successfully"),
-                };*/
-
-                self.create_new_page(content, date).await?;
-                /*if block_id_opt.is_some() {
-                    let block_id = block_id_opt.unwrap();
-                    println!("Block Id Acquired:{}", block_id);
-                    let block = get_notion_block_for_content(content_copy);
-
-                    let update_res = update_block(&api_token, block_id, block).await;
-                    match update_res {
-                        Ok(_) => println!("Saved Successfully"),
-                        Err(e) => println!("Failed because {}", e),
-                    }
-                };*/
-            };
-        } else {
-            self.create_new_page(content, date).await?;
-       }
+        // Keeps the tags added as properties rather than in the page
+        let entry = match self.get_entry_with_metadata(date).await {
+            Ok(Some(mut entry)) => {
+                entry.metadata.update_from_body(&entry.body, content);
+                entry.body = content.to_string();
+                entry
+            }
+            _ => Entry::new(date, content.to_string()),
+        };
+        self.save_entry_with_metadata(&entry).await
+    }
+
+    /// Replaces the page for the date with a new one. The old page is only archived
+    /// once the new one is in place, so a failed save loses nothing.
+    async fn save_entry_with_metadata(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        let date = entry.date;
+        let old_page = self.find_page_for_date(date).await?;
+        let new_page = self.create_new_page(entry).await?;
+        if let Some(page_id) = old_page {
+            println!("Archiving the previous page: {}", page_id);
+            let old_block = BlockId::from_str(&page_id.to_string())?;
+            if let Err(err) = delete_block(&self.api_token, &old_block).await {
+                // Two pages for the day would leave it to chance which one is read
+                let new_block = BlockId::from_str(&new_page.to_string())?;
+                let _ = delete_block(&self.api_token, &new_block).await;
+                return Err(format!("Couldn't replace the page for {}: {}", date, err).into());
+            }
+        }
         Ok(())
     }
 
      async fn get_entry(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>> {
-        if let Ok(Some(page_id)) = self.find_page_for_date(date).await {
-            if let Ok(blocks) = self.get_blocks_in_a_page(&page_id).await {
-                println!("Block found, converting to MD[GE]");
-                Ok(Some(blocks.iter().map(|b| b.to_markdown()).collect()))
-            } else {
-                Ok(Some("Block not found-GE".to_string()))
-            }
+        // Errors are passed on, so an unreachable Notion isn't taken for a missing entry
+        if let Some(page_id) = self.find_page_for_date(date).await? {
+            let blocks = self.get_blocks_in_a_page(&page_id).await?;
+            println!("Block found, converting to MD[GE]");
+            Ok(Some(blocks.iter().map(|b| b.to_markdown()).collect()))
         } else {
             println!("Page Not Found");
             Ok(None)
         }
     }
 
+    /// The entry with the latest date in its title, pages are created in any order.
     async fn get_latest_entry(&self) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>> {
-        if let Ok(Some(page_id)) = self.find_latest_page().await {
-            // todo: figure this code out, first get first block and the childran
-            // let blocks = self.client.get_block_children(&page_id, None).await?;
-            // let content = notion_to_blocks::blocks_to_string(&blocks.results);
-            // let client = std::sync::Arc::clone(&self.client);
-            if let Ok(blocks) = self.get_blocks_in_a_page(&page_id).await {
-                println!("Block found, converting to MD[LE]");
-                Ok(Some((
-                    NaiveDate::from_ymd_opt(2024, 10, 6).unwrap(),
-                    blocks.iter().map(|b| b.to_markdown()).collect(),
-                )))
-            } else {
-                println!("Block not found[LE]");
-                Ok(Some((
-                    NaiveDate::from_ymd_opt(2024, 10, 6).unwrap(),
-                    "Block not found-LE".to_string(),
-                )))
+        let Some(date) = self.find_page_dates().await?.pop() else {
+            return Ok(None);
+        };
+        Ok(self.get_entry(date).await?.map(|content| (date, content)))
+    }
+
+    async fn list_entries(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        self.find_page_dates().await
+    }
+
+    async fn get_entry_with_metadata(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<Entry>, Box<dyn Error>> {
+        let Some(page) = self.find_page(date).await? else {
+            return Ok(None);
+        };
+        let blocks = self.get_blocks_in_a_page(&page.id).await?;
+        let mut entry = Entry::new(date, blocks.iter().map(|b| b.to_markdown()).collect());
+        read_metadata_properties(&page, &mut entry.metadata);
+        Ok(Some(entry))
+    }
+}
+
+/// Location and weather as Text properties, tags as a Multi-select, and the day's average
+/// mood and energy as Number properties. Those the database in `schema` hasn't got, or has
+/// with another type, are left out.
+fn metadata_properties(
+    metadata: &EntryMetadata,
+    schema: &HashMap<String, models::properties::PropertyConfiguration>,
+) -> HashMap<String, models::properties::PropertyValue> {
+    use models::properties::{PropertyConfiguration, PropertyValue};
+
+    let mut properties = HashMap::new();
+    for (name, value) in [("Location", &metadata.location), ("Weather", &metadata.weather)] {
+        if let (Some(PropertyConfiguration::Text { id }), Some(value)) = (schema.get(name), value) {
+            let text = models::text::RichText::Text {
+                rich_text: models::text::RichTextCommon {
+                    plain_text: value.clone(),
+                    href: None,
+                    annotations: None,
+                },
+                text: models::text::Text {
+                    content: value.clone(),
+                    link: None,
+                },
+            };
+            properties.insert(
+                name.to_string(),
+                PropertyValue::Text {
+                    id: id.clone(),
+                    rich_text: vec![text],
+                },
+            );
+        }
+    }
+    if let Some(PropertyConfiguration::MultiSelect { id, .. }) = schema.get("Tags") {
+        let tags = metadata
+            .tags
+            .iter()
+            .map(|tag| models::properties::SelectedValue {
+                id: None,
+                name: Some(tag.clone()),
+                color: models::properties::Color::Default,
+            })
+            .collect();
+        properties.insert(
+            "Tags".to_string(),
+            PropertyValue::MultiSelect {
+                id: id.clone(),
+                multi_select: Some(tags),
+            },
+        );
+    }
+    for (name, value) in [("Mood", metadata.mood), ("Energy", metadata.energy)] {
+        if let Some(PropertyConfiguration::Number { id, .. }) = schema.get(name) {
+            properties.insert(
+                name.to_string(),
+                PropertyValue::Number {
+                    id: id.clone(),
+                    number: value.and_then(models::Number::from_f64),
+                },
+            );
+        }
+    }
+    properties
+}
+
+/// Takes the metadata `metadata_properties` wrote back from the page, and when it was
+/// created and last edited.
+fn read_metadata_properties(page: &models::Page, metadata: &mut EntryMetadata) {
+    use models::properties::PropertyValue;
+
+    for (name, value) in &page.properties.properties {
+        match (name.as_str(), value) {
+            ("Location" | "Weather", PropertyValue::Text { rich_text, .. }) => {
+                let text: String = rich_text.iter().map(|text| text.plain_text()).collect();
+                let text = Some(text).filter(|text| !text.trim().is_empty());
+                if name == "Location" {
+                    metadata.location = text.or(metadata.location.take());
+                } else {
+                    metadata.weather = text.or(metadata.weather.take());
+                }
             }
-        } else {
-            Ok(None)
+            ("Tags", PropertyValue::MultiSelect { multi_select, .. }) => {
+                for tag in multi_select.iter().flatten().filter_map(|tag| tag.name.clone()) {
+                    if !metadata.tags.contains(&tag) {
+                        metadata.tags.push(tag);
+                    }
+                }
+            }
+            ("Mood", PropertyValue::Number { number, .. }) => {
+                metadata.mood = number.as_ref().and_then(|n| n.as_f64()).or(metadata.mood);
+            }
+            ("Energy", PropertyValue::Number { number, .. }) => {
+                metadata.energy = number.as_ref().and_then(|n| n.as_f64()).or(metadata.energy);
+            }
+            _ => {}
         }
     }
+    metadata.created = Some(page.created_time.with_timezone(&chrono::Local));
+    metadata.updated = Some(page.last_edited_time.with_timezone(&chrono::Local));
 }
 
+#[allow(dead_code)]
 fn get_notion_block_for_content(content: String) -> models::block::CreateBlock {
     models::block::CreateBlock::Paragraph {
         paragraph: models::block::TextAndChildren {
@@ -286,19 +389,20 @@ fn get_notion_block_for_content(content: String) -> models::block::CreateBlock {
 
 const NOTION_API_VERSION: &str = "2022-02-22";
 
+#[allow(dead_code)]
 async fn delete_blocks<P>(api_token: &String, block_ids: Vec<P>) -> Result<bool, DNError>
 where
     P: ids::AsIdentifier<ids::BlockId>,
 {
     for block_id in block_ids {
-        match delete_block(api_token, &block_id).await {
-            Err(er) => eprintln!("{} {}", &block_id.as_id(), er),
-            Ok(_) => (),
-        };
+        if let Err(er) = delete_block(api_token, &block_id).await {
+            eprintln!("{} {}", &block_id.as_id(), er);
+        }
     }
     Ok(true)
 }
 
+#[allow(dead_code)]
 async fn insert_blocks_in_page<T>(
     api_token: &String,
     page_id: &ids::PageId,
@@ -365,9 +469,9 @@ where
         "children": blocks.into()
     });
     let req = client
-        .patch(&format!(
+        .patch(format!(
             "https://api.notion.com/v1/blocks/{block_id}/children",
-            block_id = page_id.to_string()
+            block_id = page_id
         ))
         .json(&json_body) // iter().map(|b| b.into()).collect())
         .build()
@@ -406,19 +510,20 @@ where
     // or make checked optional in CreateBlock
     let req = client.execute(
         client
-            .delete(&format!(
+            .delete(format!(
                 "https://api.notion.com/v1/blocks/{block_id}",
                 block_id = block_id.as_id()
             ))
             .build()
             .unwrap(),
     );
-    req.await?;
+    req.await?.error_for_status()?;
     Ok(true)
 }
 
 /// Update a block by [BlockId].
-pub async fn update_block<P, T>(
+#[allow(dead_code)]
+async fn update_block<P, T>(
     api_token: &String,
     block_id: P,
     block: T,
@@ -450,7 +555,7 @@ where
     // but I don't want to create a new type for this
     // or make checked optional in CreateBlock
     let req = client
-        .patch(&format!(
+        .patch(format!(
             "https://api.notion.com/v1/blocks/{block_id}",
             block_id = block_id.as_id()
         ))
@@ -464,6 +569,7 @@ where
 }
 
 /// An wrapper Error type for all errors produced by the [`NotionApi`](NotionApi) client.
+#[allow(dead_code, clippy::large_enum_variant)]
 #[derive(Debug, thiserror::Error)]
 enum DNError {
     #[error("Invalid Notion API Token: {}", source)]
@@ -491,6 +597,7 @@ enum DNError {
     ApiError { error: models::error::ErrorResponse },
 }
 
+#[allow(dead_code)]
 async fn make_json_request(
     client: Client,
     request: RequestBuilder,
@@ -516,7 +623,7 @@ async fn make_json_request(
     #[cfg(test)]
     {
         dbg!(serde_json::from_str::<serde_json::Value>(&json)
-            .map_err(|source| Error::JsonParseError { source })?);
+            .map_err(|source| DNError::JsonParseError { source })?);
     }
     let result =
         serde_json::from_str(&json).map_err(|source| DNError::JsonParseError { source })?;
@@ -527,6 +634,7 @@ async fn make_json_request(
     }
 }
 
+#[allow(dead_code)]
 fn get_block_id(block: &Block) -> Option<ids::BlockId> {
     match block {
         Block::Paragraph { common, .. } => Some(common.id.clone()),
@@ -47,7 +47,7 @@ fn _nested_text_to_markdown(text: &notion::models::text::Text) -> String {
 fn rich_text_to_markdown(rich_text_ref: &RichText) -> String {
     match rich_text_ref {
         RichText::Text { rich_text, text } => {
-            format_rich_text(&rich_text, &text.content, text.link.as_ref())
+            format_rich_text(rich_text, &text.content, text.link.as_ref())
         }
         RichText::Mention { rich_text, .. } => {
             // For mentions, we'll just use the plain text for now
@@ -59,7 +59,7 @@ fn rich_text_to_markdown(rich_text_ref: &RichText) -> String {
     }
 }
 
-fn vec_rich_text_to_markdown(rich_text_vec: &Vec<RichText>) -> String {
+fn vec_rich_text_to_markdown(rich_text_vec: &[RichText]) -> String {
     rich_text_vec.iter().map(rich_text_to_markdown).collect()
 }
 
@@ -138,6 +138,7 @@ fn children_to_markdown(children: &Option<Vec<Block>>) -> String {
     })
 }
 
+#[allow(dead_code)]
 pub fn blocks_to_markdown(blocks: &[Block]) -> String {
     blocks.iter().map(|block| block.to_markdown()).collect()
 }
@@ -185,7 +186,7 @@ fn process_lines(lines: &[&str]) -> (CreateBlock, usize) {
         (create_todo(line), 1)
     } else if line.starts_with("- ") {
         (create_bulleted_list_item(line), 1)
-    } else if line.starts_with(|c: char| c.is_digit(10)) && line.contains(". ") {
+    } else if line.starts_with(|c: char| c.is_ascii_digit()) && line.contains(". ") {
         (create_numbered_list_item(line), 1)
     } else if line.starts_with("> ") {
         (create_quote(line), 1)
@@ -226,7 +227,7 @@ fn create_rich_text(content: &str) -> RichText {
 }
 
 fn create_heading(line: &str, level: u8) -> CreateBlock {
-    let content = line.trim_start_matches(|c| c == '#' || c == ' ');
+    let content = line.trim_start_matches(['#', ' ']);
     let rich_text = vec![create_rich_text(content)];
     match level {
         1 => CreateBlock::Heading1 {
@@ -277,7 +278,7 @@ fn create_numbered_list_item(line: &str) -> CreateBlock {
 fn create_todo(line: &str) -> CreateBlock {
     let checked = line.starts_with("- [x] ");
     let content =
-        line.trim_start_matches(|c| c == '-' || c == '[' || c == ']' || c == ' ' || c == 'x');
+        line.trim_start_matches(['-', '[', ']', ' ', 'x']);
     CreateBlock::ToDo {
         to_do: ToDoFields {
             rich_text: vec![create_rich_text(content)],
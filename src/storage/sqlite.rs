@@ -0,0 +1,559 @@
+// storage/sqlite.rs
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use diary_app::{parse_sections, Entry, EntryMetadata, Storage};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS entries (
+    date TEXT PRIMARY KEY,
+    content TEXT NOT NULL,
+    city TEXT,
+    weather TEXT,
+    mood REAL,
+    energy REAL,
+    tags TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sections (
+    date TEXT NOT NULL REFERENCES entries(date) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    time TEXT,
+    content TEXT NOT NULL,
+    PRIMARY KEY (date, position)
+);
+CREATE TABLE IF NOT EXISTS pending (
+    date TEXT PRIMARY KEY
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS sections_fts USING fts5(
+    content,
+    date UNINDEXED,
+    time UNINDEXED,
+    tokenize = 'unicode61'
+);
+"#;
+
+/// A section of an entry matching a search query.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub date: NaiveDate,
+    pub time: Option<NaiveTime>,
+    pub snippet: String,
+}
+
+/// What [`SqliteStorage::sync`] did.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Entries saved while the backend was unreachable, now stored there
+    pub pushed: usize,
+    /// Entries changed in the backend, now updated in the index
+    pub pulled: usize,
+}
+
+/// Entries, their metadata and a full-text index in a single SQLite file.
+///
+/// On its own it is a primary store. Wrapped around another backend it becomes a
+/// local cache and index: writes go to both, reads prefer the backend and fall back
+/// to the cached copy when the backend can't be reached. A write the backend can't
+/// take is kept as pending until [`SqliteStorage::sync`] gets it there.
+pub struct SqliteStorage {
+    conn: Connection,
+    backend: Option<Box<dyn Storage>>,
+}
+
+impl SqliteStorage {
+    pub fn new(db_path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        if let Some(parent) = db_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(db_path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        // Databases from before mood tracking and tags lack their columns
+        for (column, kind) in [("mood", "REAL"), ("energy", "REAL"), ("tags", "TEXT")] {
+            let exists = conn
+                .prepare("SELECT 1 FROM pragma_table_info('entries') WHERE name = ?1")?
+                .exists(params![column])?;
+            if !exists {
+                conn.execute(
+                    &format!("ALTER TABLE entries ADD COLUMN {} {}", column, kind),
+                    [],
+                )?;
+            }
+        }
+        Ok(SqliteStorage {
+            conn,
+            backend: None,
+        })
+    }
+
+    pub fn with_backend(
+        db_path: PathBuf,
+        backend: Box<dyn Storage>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut storage = Self::new(db_path)?;
+        storage.backend = Some(backend);
+        Ok(storage)
+    }
+
+    /// Stores `content` for `date` and rebuilds its sections and search index. The
+    /// metadata that doesn't come from the body is kept.
+    pub fn index_entry(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
+        let (previous, mut metadata) = match self.cached_entry_with_metadata(date)? {
+            Some(entry) => (entry.body, entry.metadata),
+            None => (String::new(), EntryMetadata::default()),
+        };
+        metadata.update_from_body(&previous, content);
+        self.store_entry(&Entry {
+            date,
+            body: content.to_string(),
+            metadata,
+        })
+    }
+
+    /// Stores `entry` with its metadata as given and rebuilds its sections and search
+    /// index. The time it was first stored is kept when it hasn't got one, and it's
+    /// updated now when it hasn't got that.
+    fn store_entry(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        let date = entry.date;
+        let content = entry.body.as_str();
+        let date_key = date.format("%Y-%m-%d").to_string();
+        let metadata = &entry.metadata;
+        let now = Local::now().to_rfc3339();
+        let created = metadata.created.map(|created| created.to_rfc3339());
+        let updated = metadata
+            .updated
+            .map_or(now.clone(), |updated| updated.to_rfc3339());
+
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO entries (date, content, city, weather, mood, energy, tags, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, COALESCE(?8, ?9), ?10)
+             ON CONFLICT(date) DO UPDATE SET
+                content = excluded.content,
+                city = excluded.city,
+                weather = excluded.weather,
+                mood = excluded.mood,
+                energy = excluded.energy,
+                tags = excluded.tags,
+                created_at = COALESCE(?8, created_at),
+                updated_at = excluded.updated_at",
+            params![
+                date_key,
+                content,
+                metadata.location,
+                metadata.weather,
+                metadata.mood,
+                metadata.energy,
+                serde_json::to_string(&metadata.tags)?,
+                created,
+                now,
+                updated
+            ],
+        )?;
+        tx.execute("DELETE FROM sections WHERE date = ?1", params![date_key])?;
+        tx.execute(
+            "DELETE FROM sections_fts WHERE date = ?1",
+            params![date_key],
+        )?;
+        for (position, section) in parse_sections(date, content).into_iter().enumerate() {
+            let time = section.start.map(|start| start.format("%H:%M").to_string());
+            let body = section.body;
+            tx.execute(
+                "INSERT INTO sections (date, position, time, content) VALUES (?1, ?2, ?3, ?4)",
+                params![date_key, position as i64, time, body],
+            )?;
+            tx.execute(
+                "INSERT INTO sections_fts (content, date, time) VALUES (?1, ?2, ?3)",
+                params![body, date_key, time],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Runs an FTS5 `MATCH` expression, newest entries first.
+    pub fn search(
+        &self,
+        fts_query: &str,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+        let mut statement = self.conn.prepare(
+            "SELECT date, time, snippet(sections_fts, 0, '**', '**', '…', 16)
+             FROM sections_fts
+             WHERE sections_fts MATCH ?1
+               AND (?2 IS NULL OR date >= ?2)
+               AND (?3 IS NULL OR date <= ?3)
+             ORDER BY date DESC, time DESC
+             LIMIT ?4",
+        )?;
+        let from = from.map(|d| d.format("%Y-%m-%d").to_string());
+        let to = to.map(|d| d.format("%Y-%m-%d").to_string());
+        let rows = statement.query_map(params![fts_query, from, to, limit as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut hits = Vec::new();
+        for row in rows {
+            let (date, time, snippet) = row?;
+            hits.push(SearchHit {
+                date: NaiveDate::parse_from_str(&date, "%Y-%m-%d")?,
+                time: time.and_then(|t| NaiveTime::parse_from_str(&t, "%H:%M").ok()),
+                snippet,
+            });
+        }
+        Ok(hits)
+    }
+
+    fn cached_entry(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT content FROM entries WHERE date = ?1",
+                params![date.format("%Y-%m-%d").to_string()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn cached_entry_with_metadata(&self, date: NaiveDate) -> Result<Option<Entry>, Box<dyn Error>> {
+        type Row = (
+            String,
+            Option<String>,
+            Option<String>,
+            Option<f64>,
+            Option<f64>,
+            Option<String>,
+            String,
+            String,
+        );
+        let row: Option<Row> = self
+            .conn
+            .query_row(
+                "SELECT content, city, weather, mood, energy, tags, created_at, updated_at
+                 FROM entries WHERE date = ?1",
+                params![date.format("%Y-%m-%d").to_string()],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                        row.get(7)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((body, location, weather, mood, energy, tags, created, updated)) = row else {
+            return Ok(None);
+        };
+        let mut entry = Entry::new(date, body);
+        let metadata = &mut entry.metadata;
+        metadata.location = location;
+        metadata.weather = weather;
+        metadata.mood = mood;
+        metadata.energy = energy;
+        // Rows from before tags were stored keep the body's
+        if let Some(tags) = tags {
+            metadata.tags = serde_json::from_str(&tags)?;
+        }
+        metadata.created = Some(DateTime::parse_from_rfc3339(&created)?.with_timezone(&Local));
+        metadata.updated = Some(DateTime::parse_from_rfc3339(&updated)?.with_timezone(&Local));
+        Ok(Some(entry))
+    }
+
+    fn cached_entry_at(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>> {
+        Ok(self.cached_entry(date)?.map(|content| (date, content)))
+    }
+
+    fn cached_latest_entry(&self) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>> {
+        let latest: Option<(String, String)> = self
+            .conn
+            .query_row(
+                "SELECT date, content FROM entries ORDER BY date DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        match latest {
+            Some((date, content)) => Ok(Some((
+                NaiveDate::parse_from_str(&date, "%Y-%m-%d")?,
+                content,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Pushes pending entries to the backend, then pulls every backend entry into the index.
+    pub async fn sync(&self) -> Result<SyncReport, Box<dyn Error>> {
+        let Some(backend) = &self.backend else {
+            return Err("SQLite is the primary storage, there is nothing to sync with".into());
+        };
+        let mut report = SyncReport::default();
+        for date in self.pending_dates()? {
+            if let Some(entry) = self.cached_entry_with_metadata(date)? {
+                backend.save_entry_with_metadata(&entry).await?;
+                report.pushed += 1;
+            }
+            self.set_pending(date, false)?;
+        }
+        for date in backend.list_entries().await? {
+            if let Some(content) = backend.get_entry(date).await? {
+                if self.cached_entry(date)?.as_deref() != Some(content.as_str()) {
+                    self.index_entry(date, &content)?;
+                    report.pulled += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn pending_dates(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT date FROM pending ORDER BY date")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut dates = Vec::new();
+        for row in rows {
+            dates.push(NaiveDate::parse_from_str(&row?, "%Y-%m-%d")?);
+        }
+        Ok(dates)
+    }
+
+    fn is_pending(&self, date: NaiveDate) -> Result<bool, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT 1 FROM pending WHERE date = ?1",
+                params![date.format("%Y-%m-%d").to_string()],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    fn set_pending(&self, date: NaiveDate, pending: bool) -> Result<(), Box<dyn Error>> {
+        let date_key = date.format("%Y-%m-%d").to_string();
+        if pending {
+            self.conn.execute(
+                "INSERT OR IGNORE INTO pending (date) VALUES (?1)",
+                params![date_key],
+            )?;
+        } else {
+            self.conn
+                .execute("DELETE FROM pending WHERE date = ?1", params![date_key])?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the entry pending when the backend couldn't take it.
+    fn mark_saved(
+        &self,
+        date: NaiveDate,
+        saved: Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        match saved {
+            Ok(()) => self.set_pending(date, false),
+            Err(err) => {
+                eprintln!(
+                    "Storage unavailable, entry kept locally until `diary_app sync`: {}",
+                    err
+                );
+                self.set_pending(date, true)
+            }
+        }
+    }
+
+    fn cached_dates(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT date FROM entries ORDER BY date")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut dates = Vec::new();
+        for row in rows {
+            dates.push(NaiveDate::parse_from_str(&row?, "%Y-%m-%d")?);
+        }
+        Ok(dates)
+    }
+
+    /// Keeps the cache in step with what the backend returned.
+    fn refresh(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
+        if self.cached_entry(date)?.as_deref() != Some(content) {
+            self.index_entry(date, content)?;
+        }
+        Ok(())
+    }
+
+    fn refresh_with_metadata(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        if self.cached_entry_with_metadata(entry.date)?.as_ref() != Some(entry) {
+            self.store_entry(entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for SqliteStorage {
+    async fn save_entry(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
+        // Index first so the entry is kept locally even if the backend is down
+        self.index_entry(date, content)?;
+        if let Some(backend) = &self.backend {
+            let saved = backend.save_entry(date, content).await;
+            self.mark_saved(date, saved)?;
+        }
+        Ok(())
+    }
+
+    async fn get_entry(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>> {
+        let Some(backend) = &self.backend else {
+            return self.cached_entry(date);
+        };
+        // The backend hasn't got the latest version yet
+        if self.is_pending(date)? {
+            return self.cached_entry(date);
+        }
+        match backend.get_entry(date).await {
+            Ok(Some(content)) => {
+                self.refresh(date, &content)?;
+                Ok(Some(content))
+            }
+            Ok(None) => Ok(None),
+            Err(err) => {
+                eprintln!("Storage unavailable, using cached entry: {}", err);
+                self.cached_entry(date)
+            }
+        }
+    }
+
+    async fn get_latest_entry(&self) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>> {
+        let Some(backend) = &self.backend else {
+            return self.cached_latest_entry();
+        };
+        let latest = match backend.get_latest_entry().await {
+            Ok(latest) => latest,
+            Err(err) => {
+                eprintln!("Storage unavailable, using cached entry: {}", err);
+                return self.cached_latest_entry();
+            }
+        };
+        // A pending entry may be newer than anything the backend has
+        let newest_pending = self.pending_dates()?.pop();
+        match (latest, newest_pending) {
+            (Some((date, _)), Some(pending)) if pending >= date => self.cached_entry_at(pending),
+            (None, Some(pending)) => self.cached_entry_at(pending),
+            (Some((date, content)), _) => {
+                self.refresh(date, &content)?;
+                Ok(Some((date, content)))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    async fn list_entries(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        let Some(backend) = &self.backend else {
+            return self.cached_dates();
+        };
+        match backend.list_entries().await {
+            Ok(mut dates) => {
+                // Include what's only here until it's synced
+                dates.extend(self.pending_dates()?);
+                dates.sort();
+                dates.dedup();
+                Ok(dates)
+            }
+            Err(err) => {
+                eprintln!("Storage unavailable, listing cached entries: {}", err);
+                self.cached_dates()
+            }
+        }
+    }
+
+    async fn get_entry_with_metadata(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<Entry>, Box<dyn Error>> {
+        let Some(backend) = &self.backend else {
+            return self.cached_entry_with_metadata(date);
+        };
+        if self.is_pending(date)? {
+            return self.cached_entry_with_metadata(date);
+        }
+        match backend.get_entry_with_metadata(date).await {
+            Ok(Some(entry)) => {
+                self.refresh_with_metadata(&entry)?;
+                Ok(Some(entry))
+            }
+            Ok(None) => Ok(None),
+            Err(err) => {
+                eprintln!("Storage unavailable, using cached entry: {}", err);
+                self.cached_entry_with_metadata(date)
+            }
+        }
+    }
+
+    async fn save_entry_with_metadata(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        let mut saved = entry.clone();
+        saved.metadata.updated = Some(Local::now());
+        self.store_entry(&saved)?;
+        if let Some(backend) = &self.backend {
+            let saved = backend.save_entry_with_metadata(entry).await;
+            self.mark_saved(entry.date, saved)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_metadata_as_the_primary_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SqliteStorage::new(dir.path().join("diary.sqlite3")).unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        let mut entry = Entry::new(date, "Shipped it #work\n".to_string());
+        entry.metadata.tags.push("travel".to_string());
+        let created = DateTime::parse_from_rfc3339("2024-03-05T07:00:00+01:00")
+            .unwrap()
+            .with_timezone(&Local);
+        entry.metadata.created = Some(created);
+        storage.save_entry_with_metadata(&entry).await.unwrap();
+
+        let stored = storage
+            .get_entry_with_metadata(date)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.metadata.tags, ["work", "travel"]);
+        assert_eq!(stored.metadata.created, Some(created));
+        assert!(stored.metadata.updated.is_some());
+
+        // Saving the body alone keeps what didn't come from it
+        storage.save_entry(date, "Shipped it\n").await.unwrap();
+        let stored = storage
+            .get_entry_with_metadata(date)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.metadata.tags, ["travel"]);
+        assert_eq!(stored.metadata.created, Some(created));
+    }
+}
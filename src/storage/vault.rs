@@ -0,0 +1,289 @@
+// storage/vault.rs
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::Mapping;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use diary_app::{Entry, Storage};
+
+use crate::storage::frontmatter::{read_note, split_frontmatter, updated_entry, write_note};
+
+/// Which note-taking app owns the vault, decides where its settings live.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VaultKind {
+    Obsidian,
+    Logseq,
+}
+
+/// `.obsidian/daily-notes.json`, written by the core Daily Notes plugin.
+#[derive(Deserialize, Default)]
+struct ObsidianDailyNotes {
+    folder: Option<String>,
+    format: Option<String>,
+}
+
+/// Stores each day as a native daily note inside an Obsidian or Logseq vault.
+pub struct VaultStorage {
+    notes_path: PathBuf,
+    date_format: String,
+}
+
+impl VaultStorage {
+    pub fn new(vault_path: PathBuf) -> Self {
+        let kind = Self::detect_kind(&vault_path);
+        let (folder, date_format) = match kind {
+            VaultKind::Obsidian => Self::obsidian_settings(&vault_path),
+            VaultKind::Logseq => Self::logseq_settings(&vault_path),
+        };
+        eprintln!(
+            "Using {:?} vault, daily notes in '{}' named as '{}'",
+            kind, folder, date_format
+        );
+        VaultStorage {
+            notes_path: vault_path.join(folder),
+            date_format,
+        }
+    }
+
+    fn detect_kind(vault_path: &Path) -> VaultKind {
+        if vault_path.join("logseq").join("config.edn").exists()
+            && !vault_path.join(".obsidian").exists()
+        {
+            VaultKind::Logseq
+        } else {
+            VaultKind::Obsidian
+        }
+    }
+
+    fn obsidian_settings(vault_path: &Path) -> (String, String) {
+        let settings: ObsidianDailyNotes =
+            fs::read_to_string(vault_path.join(".obsidian").join("daily-notes.json"))
+                .ok()
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default();
+        let format = settings
+            .format
+            .filter(|f| !f.trim().is_empty())
+            .unwrap_or_else(|| "YYYY-MM-DD".to_string());
+        (
+            settings.folder.unwrap_or_default(),
+            moment_to_chrono(&format),
+        )
+    }
+
+    fn logseq_settings(vault_path: &Path) -> (String, String) {
+        let config =
+            fs::read_to_string(vault_path.join("logseq").join("config.edn")).unwrap_or_default();
+        let edn_string = |key: &str| {
+            Regex::new(&format!(r#":{}\s+"([^"]*)""#, regex::escape(key)))
+                .unwrap()
+                .captures(&config)
+                .map(|c| c[1].to_string())
+        };
+        let folder = edn_string("journals-directory").unwrap_or_else(|| "journals".to_string());
+        let format =
+            edn_string("journal/file-name-format").unwrap_or_else(|| "yyyy_MM_dd".to_string());
+        (folder, java_to_chrono(&format))
+    }
+
+    fn note_path(&self, date: NaiveDate) -> PathBuf {
+        self.notes_path
+            .join(format!("{}.md", date.format(&self.date_format)))
+    }
+
+    fn collect_notes(dir: &Path, notes: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+        for entry in fs::read_dir(dir)?.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                Self::collect_notes(&path, notes)?;
+            } else if path.extension().map(|ext| ext == "md").unwrap_or(false) {
+                notes.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the date back out of a note path, the format may contain `/` for nested folders.
+    fn date_of(&self, note: &Path) -> Option<NaiveDate> {
+        let relative = note.strip_prefix(&self.notes_path).ok()?.with_extension("");
+        let relative = relative.to_str()?.replace(std::path::MAIN_SEPARATOR, "/");
+        NaiveDate::parse_from_str(&relative, &self.date_format).ok()
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for VaultStorage {
+    async fn save_entry(&self, date: NaiveDate, content: &str) -> Result<(), Box<dyn Error>> {
+        let entry = updated_entry(&self.note_path(date), date, content)?;
+        self.save_entry_with_metadata(&entry).await
+    }
+
+    async fn get_entry(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(read_note(&self.note_path(date), date)?.map(|entry| entry.body))
+    }
+
+    async fn get_latest_entry(&self) -> Result<Option<(NaiveDate, String)>, Box<dyn Error>> {
+        let mut notes = Vec::new();
+        if self.notes_path.exists() {
+            Self::collect_notes(&self.notes_path, &mut notes)?;
+        }
+        let latest = notes
+            .iter()
+            .filter_map(|note| self.date_of(note).map(|date| (date, note)))
+            .max_by_key(|(date, _)| *date);
+
+        if let Some((date, note)) = latest {
+            let note = fs::read_to_string(note)?;
+            Ok(Some((date, split_frontmatter(&note).1.to_string())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn list_entries(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+        let mut notes = Vec::new();
+        if self.notes_path.exists() {
+            Self::collect_notes(&self.notes_path, &mut notes)?;
+        }
+        let mut dates: Vec<NaiveDate> =
+            notes.iter().filter_map(|note| self.date_of(note)).collect();
+        dates.sort();
+        Ok(dates)
+    }
+
+    async fn get_entry_with_metadata(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<Entry>, Box<dyn Error>> {
+        read_note(&self.note_path(date), date)
+    }
+
+    /// Writes the metadata into the note's frontmatter with its `date`, so the vault can
+    /// query it. Whatever was added from inside the vault stays, the body is written
+    /// untouched so `[[wikilinks]]` keep working.
+    async fn save_entry_with_metadata(&self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        let file_path = self.note_path(entry.date);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let date = Mapping::from_iter([(
+            "date".into(),
+            entry.date.format("%Y-%m-%d").to_string().into(),
+        )]);
+        write_note(&file_path, entry, date)
+    }
+}
+
+/// Converts a moment.js date format, as used by Obsidian, into a chrono one.
+fn moment_to_chrono(format: &str) -> String {
+    convert_date_format(
+        format,
+        &[
+            ("YYYY", "%Y"),
+            ("YY", "%y"),
+            ("MMMM", "%B"),
+            ("MMM", "%b"),
+            ("MM", "%m"),
+            ("M", "%-m"),
+            ("DDDD", "%j"),
+            ("DD", "%d"),
+            ("D", "%-d"),
+            ("dddd", "%A"),
+            ("ddd", "%a"),
+            ("ww", "%V"),
+        ],
+    )
+}
+
+/// Converts a Java style date format, as used by Logseq, into a chrono one.
+fn java_to_chrono(format: &str) -> String {
+    convert_date_format(
+        format,
+        &[
+            ("yyyy", "%Y"),
+            ("yy", "%y"),
+            ("MMMM", "%B"),
+            ("MMM", "%b"),
+            ("MM", "%m"),
+            ("M", "%-m"),
+            ("dd", "%d"),
+            ("d", "%-d"),
+            ("EEEE", "%A"),
+            ("EEE", "%a"),
+        ],
+    )
+}
+
+fn convert_date_format(format: &str, tokens: &[(&str, &str)]) -> String {
+    let mut converted = String::new();
+    let mut rest = format;
+    'outer: while let Some(c) = rest.chars().next() {
+        // moment escapes literals with [..], Java with '..'
+        if c == '[' || c == '\'' {
+            let close = if c == '[' { ']' } else { '\'' };
+            if let Some(end) = rest[1..].find(close) {
+                converted.push_str(&rest[1..end + 1].replace('%', "%%"));
+                rest = &rest[end + 2..];
+                continue;
+            }
+        }
+        for (token, chrono_token) in tokens {
+            if let Some(after) = rest.strip_prefix(token) {
+                converted.push_str(chrono_token);
+                rest = after;
+                continue 'outer;
+            }
+        }
+        if c == '%' {
+            converted.push('%');
+        }
+        converted.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_daily_note_formats() {
+        assert_eq!(moment_to_chrono("YYYY-MM-DD"), "%Y-%m-%d");
+        assert_eq!(moment_to_chrono("dddd, MMMM D YY"), "%A, %B %-d %y");
+        assert_eq!(java_to_chrono("yyyy_MM_dd"), "%Y_%m_%d");
+        assert_eq!(java_to_chrono("EEE d MMM"), "%a %-d %b");
+    }
+
+    #[test]
+    fn keeps_literals_as_they_are() {
+        assert_eq!(moment_to_chrono("[Week] ww [of] YYYY"), "Week %V of %Y");
+        assert_eq!(java_to_chrono("'Day' d 'of' yyyy"), "Day %-d of %Y");
+        assert_eq!(moment_to_chrono("YYYY [100%]"), "%Y 100%%");
+        assert_eq!(moment_to_chrono("DD%"), "%d%%");
+        // An unclosed bracket is just a character
+        assert_eq!(moment_to_chrono("[YYYY"), "[%Y");
+    }
+
+    #[test]
+    fn finds_the_date_of_nested_notes() {
+        let date_format = moment_to_chrono("YYYY/MM-MMMM/YYYY-MM-DD-dddd");
+        assert_eq!(date_format, "%Y/%m-%B/%Y-%m-%d-%A");
+        let vault = VaultStorage {
+            notes_path: PathBuf::from("/vault/Daily"),
+            date_format,
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        let note = vault.note_path(date);
+        assert_eq!(
+            note,
+            PathBuf::from("/vault/Daily/2024/03-March/2024-03-05-Tuesday.md")
+        );
+        assert_eq!(vault.date_of(&note), Some(date));
+        assert_eq!(vault.date_of(Path::new("/elsewhere/2024-03-05.md")), None);
+    }
+}
@@ -1,38 +1,301 @@
 // task_manager.rs
-use std::fs::{File, OpenOptions};
-use std::io::{self, Write, Read};
-use std::path::Path;
-use chrono::Local;
-use std::process::Command;
-
-pub struct TaskManager {
-    dir: String,
+use chrono::NaiveDate;
+use diary_app::{body_tags, Storage};
+use regex::Regex;
+use std::error::Error;
+
+/// A checkbox line, `- [ ] text` or `- [x] text`.
+const TASK_LINE: &str = r"^\s*[-*] \[([ xX])\]\s+(.*\S)\s*$";
+/// The note on a carried task saying which day it was first written down.
+const FROM_NOTE: &str = r"\s*\(from (\d{4}-\d{2}-\d{2})\)";
+/// The note `done` leaves on a task it ticks off.
+const DONE_NOTE: &str = r"\s*\bdone:(\d{4}-\d{2}-\d{2})\b";
+const DUE_FIELD: &str = r"\bdue:(\d{4}-\d{2}-\d{2})\b";
+
+/// A `- [ ]` item in an entry. Tags (`#errand`) and a due date (`due:2024-03-08`) are
+/// written in its text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    /// The entry it's in
+    pub date: NaiveDate,
+    /// Its place among the tasks of that entry, counted from 1
+    pub number: usize,
+    /// What's to be done, without the from and done notes
+    pub text: String,
+    pub done: bool,
+    /// The day it was first written down, before `date` for carried tasks
+    pub created: NaiveDate,
+    pub completed: Option<NaiveDate>,
+    /// Without the `#`, as in the entry's metadata
+    pub tags: Vec<String>,
+    pub due: Option<NaiveDate>,
+}
+
+impl Task {
+    /// How the task is named on the command line, e.g. `2024-03-05:2`.
+    pub fn id(&self) -> String {
+        format!("{}:{}", self.date.format("%Y-%m-%d"), self.number)
+    }
 }
 
-impl TaskManager {
-    pub fn new(dir: &str) -> Self {
-        TaskManager {
-            dir: dir.to_string(),
+/// Reads a task id back into the entry date and task number.
+pub fn parse_task_id(id: &str) -> Option<(NaiveDate, usize)> {
+    let (date, number) = id.split_once(':')?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let number = number.parse().ok().filter(|number| *number > 0)?;
+    Some((date, number))
+}
+
+/// The tasks in `entry`, the entry for `date`, in order.
+pub fn parse_tasks(date: NaiveDate, entry: &str) -> Vec<Task> {
+    let task_line = Regex::new(TASK_LINE).unwrap();
+    let from_note = Regex::new(FROM_NOTE).unwrap();
+    let done_note = Regex::new(DONE_NOTE).unwrap();
+    let due_field = Regex::new(DUE_FIELD).unwrap();
+    let note_date = |note: &Regex, text: &str| {
+        note.captures(text)
+            .and_then(|captures| NaiveDate::parse_from_str(&captures[1], "%Y-%m-%d").ok())
+    };
+
+    let mut tasks = Vec::new();
+    for captures in entry.lines().filter_map(|line| task_line.captures(line)) {
+        let done = &captures[1] != " ";
+        let raw = &captures[2];
+        let without_from = from_note.replace_all(raw, "");
+        let text = done_note.replace_all(&without_from, "").trim().to_string();
+        if text.is_empty() {
+            continue;
+        }
+        tasks.push(Task {
+            date,
+            number: tasks.len() + 1,
+            done,
+            created: note_date(&from_note, raw).unwrap_or(date),
+            // Ticked off in the editor rather than with `done`, so the entry's day
+            completed: done.then(|| note_date(&done_note, raw).unwrap_or(date)),
+            tags: body_tags(&text),
+            due: note_date(&due_field, &text),
+            text,
+        });
+    }
+    tasks
+}
+
+/// Tasks across the diary, read from the entries and written back into them.
+pub struct TaskManager<'a> {
+    storage: &'a dyn Storage,
+}
+
+impl<'a> TaskManager<'a> {
+    pub fn new(storage: &'a dyn Storage) -> Self {
+        TaskManager { storage }
+    }
+
+    /// The tasks in the entries between `from` and `to`, oldest first. An open task
+    /// carried from day to day shows up once, as its latest copy.
+    pub async fn tasks(
+        &self,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Vec<Task>, Box<dyn Error>> {
+        let mut tasks: Vec<Task> = Vec::new();
+        for date in self.storage.list_entries().await? {
+            if from.is_some_and(|from| date < from) || to.is_some_and(|to| date > to) {
+                continue;
+            }
+            let Some(entry) = self.storage.get_entry(date).await? else {
+                continue;
+            };
+            for task in parse_tasks(date, &entry) {
+                let carried = tasks
+                    .iter()
+                    .position(|earlier| !earlier.done && earlier.text == task.text);
+                if let Some(at) = carried {
+                    let earlier = tasks.remove(at);
+                    tasks.push(Task {
+                        created: earlier.created.min(task.created),
+                        ..task
+                    });
+                } else {
+                    tasks.push(task);
+                }
+            }
+        }
+        Ok(tasks)
+    }
+
+    /// Ticks off task `number` of the entry for `date`, noting `on` as the day it was done.
+    pub async fn complete(
+        &self,
+        date: NaiveDate,
+        number: usize,
+        on: NaiveDate,
+    ) -> Result<Task, Box<dyn Error>> {
+        let entry = self
+            .storage
+            .get_entry(date)
+            .await?
+            .ok_or_else(|| format!("No entry for {}", date))?;
+        let task = number
+            .checked_sub(1)
+            .and_then(|at| parse_tasks(date, &entry).into_iter().nth(at))
+            .ok_or_else(|| format!("The entry for {} has no task {}", date, number))?;
+        if task.done {
+            return Err(format!("`{}` is done already", task.text).into());
         }
+
+        let mut updated = String::new();
+        let mut seen = 0;
+        for line in entry.split_inclusive('\n') {
+            let body = line.trim_end_matches(['\r', '\n']);
+            // Blank tasks aren't counted by `parse_tasks`, so they aren't here either
+            if !parse_tasks(date, body).is_empty() {
+                seen += 1;
+                if seen == number {
+                    let ticked = body.replacen("[ ]", "[x]", 1);
+                    updated.push_str(&format!("{} done:{}", ticked, on.format("%Y-%m-%d")));
+                    updated.push_str(&line[body.len()..]);
+                    continue;
+                }
+            }
+            updated.push_str(line);
+        }
+        self.storage.save_entry(date, &updated).await?;
+        Ok(parse_tasks(date, &updated).swap_remove(number - 1))
     }
 
-    pub fn prompt_tasks(&self) {
-        let date = Local::now().format("%Y-%m-%d").to_string();
-        let file_path = format!("{}/{}.md", self.dir, date);
+    /// Adds an open task to the entry for `date`, below the tasks it has already.
+    pub async fn add(&self, date: NaiveDate, text: &str) -> Result<Task, Box<dyn Error>> {
+        let new_line = format!("- [ ] {}\n", text.trim());
+        let Some(new_task) = parse_tasks(date, &new_line).pop() else {
+            return Err("The task is empty".into());
+        };
+
+        let entry = self.storage.get_entry(date).await?.unwrap_or_default();
+        let task_line = Regex::new(TASK_LINE).unwrap();
+        let mut lines: Vec<String> = entry.split_inclusive('\n').map(str::to_string).collect();
+        let last_task = lines
+            .iter()
+            .rposition(|line| task_line.is_match(line.trim_end_matches(['\r', '\n'])));
+        match last_task {
+            Some(at) => {
+                if !lines[at].ends_with('\n') {
+                    lines[at].push('\n');
+                }
+                lines.insert(at + 1, new_line);
+            }
+            None if entry.trim().is_empty() => lines = vec![new_line],
+            None => {
+                let end = lines.pop().unwrap_or_default();
+                lines.push(format!("{}\n\n", end.trim_end()));
+                lines.push(new_line);
+            }
+        }
+        let updated = lines.concat();
+        self.storage.save_entry(date, &updated).await?;
+
+        let task = parse_tasks(date, &updated)
+            .into_iter()
+            .rfind(|task| task.text == new_task.text)
+            .expect("the task was just added");
+        Ok(task)
+    }
+}
 
-        if !Path::new(&file_path).exists() {
-            let mut file = File::create(&file_path).expect("Failed to create tasks file");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::local::LocalStorage;
 
-            writeln!(file, "# Tasks for {}", date).expect("Failed to write to tasks file");
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, 5).unwrap()
+    }
 
-            Command::new("x-terminal-emulator")
-                .arg("-e")
-                .arg("vim")
-                .arg(&file_path)
-                .status()
-                .expect("Failed to open vim for tasks in foreground");
+    #[test]
+    fn reads_the_task_fields() {
+        let entry = "# Tasks for today\n- [ ] Call the bank #errand due:2024-03-08 (from 2024-03-01)\n- [x] Workout done:2024-03-06\n  * [X]  Read  \n- [ ]\nnot - [ ] a task\n";
+        let tasks = parse_tasks(date(), entry);
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[0].text, "Call the bank #errand due:2024-03-08");
+        assert!(!tasks[0].done);
+        assert_eq!(
+            tasks[0].created,
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()
+        );
+        assert_eq!(tasks[0].tags, vec!["errand"]);
+        assert_eq!(tasks[0].due, NaiveDate::from_ymd_opt(2024, 3, 8));
+        assert_eq!(tasks[1].text, "Workout");
+        assert_eq!(tasks[1].completed, NaiveDate::from_ymd_opt(2024, 3, 6));
+        assert_eq!(tasks[2].text, "Read");
+        assert_eq!(tasks[2].completed, Some(date()));
+        assert_eq!(tasks[2].id(), "2024-03-05:3");
+    }
 
-            println!("Tasks saved to {}", file_path);
+    #[test]
+    fn reads_tags_like_the_entry_does() {
+        let tasks = parse_tasks(date(), "- [ ] Fix issue #12 for #work/infra and #on-call\n");
+        assert_eq!(tasks[0].tags, vec!["work/infra", "on-call"]);
+        assert_eq!(tasks[0].tags, diary_app::body_tags(&tasks[0].text));
+    }
+
+    #[test]
+    fn parses_task_ids() {
+        assert_eq!(parse_task_id("2024-03-05:2"), Some((date(), 2)));
+        assert_eq!(parse_task_id("2024-03-05:0"), None);
+        assert_eq!(parse_task_id("Call the bank"), None);
+    }
+
+    #[tokio::test]
+    async fn ticks_off_only_the_chosen_task() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path().to_path_buf());
+        let entry = "# Tasks\n- [ ] Call the bank\n- [ ]\n- [ ] Workout\r\nSome - [ ] words\n";
+        storage.save_entry(date(), entry).await.unwrap();
+        let manager = TaskManager::new(&storage);
+        let on = NaiveDate::from_ymd_opt(2024, 3, 6).unwrap();
+
+        let task = manager.complete(date(), 2, on).await.unwrap();
+        assert_eq!(task.text, "Workout");
+        assert_eq!(task.completed, Some(on));
+        assert_eq!(
+            storage.get_entry(date()).await.unwrap().unwrap(),
+            "# Tasks\n- [ ] Call the bank\n- [ ]\n- [x] Workout done:2024-03-06\r\nSome - [ ] words\n"
+        );
+
+        for number in [0, 3] {
+            assert!(manager.complete(date(), number, on).await.is_err());
         }
+        assert!(manager.complete(date(), 2, on).await.is_err());
+        assert!(manager.complete(on, 1, on).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn adds_tasks_below_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path().to_path_buf());
+        let manager = TaskManager::new(&storage);
+
+        // Today has no entry yet
+        let task = manager
+            .add(date(), " Call the bank #errand ")
+            .await
+            .unwrap();
+        assert_eq!(task.id(), "2024-03-05:1");
+        assert_eq!(task.tags, vec!["errand"]);
+        assert_eq!(
+            storage.get_entry(date()).await.unwrap().unwrap(),
+            "- [ ] Call the bank #errand\n"
+        );
+
+        storage
+            .save_entry(date(), "- [x] Workout\n\nNotes")
+            .await
+            .unwrap();
+        manager.add(date(), "Read").await.unwrap();
+        assert_eq!(
+            storage.get_entry(date()).await.unwrap().unwrap(),
+            "- [x] Workout\n- [ ] Read\n\nNotes"
+        );
+        assert!(manager.add(date(), "  ").await.is_err());
     }
 }
@@ -0,0 +1,132 @@
+// template.rs
+use chrono::{Datelike, Local, NaiveDate};
+use diary_app::{TemplateConfig, CITY_PREFIX, WEATHER_PREFIX};
+use handlebars::Handlebars;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ConfigManager;
+
+/// The section a check-in adds, a timestamp heading to write under.
+const CHECK_IN_TEMPLATE: &str = "## {{time}}\n\n";
+/// One line each, read from `prompts.txt` in the templates folder when it exists.
+const PROMPTS_FILE: &str = "prompts.txt";
+/// Writing prompts for `{{prompt}}`, a different one every day.
+const WRITING_PROMPTS: &[&str] = &[
+    "What would make today a good day?",
+    "What are you looking forward to?",
+    "What's been on your mind lately?",
+    "Who could you reach out to today?",
+    "What did you learn yesterday?",
+    "What's one thing you'd like to let go of?",
+    "What are you grateful for this morning?",
+    "What's the one thing that has to get done today?",
+    "How are you feeling, honestly?",
+    "What would you tell yourself a year ago?",
+    "What drained your energy recently, and what restored it?",
+    "What small thing could you do for your health today?",
+];
+
+/// What a template can fill in. Callers set what they know about, the date, weekday,
+/// time and prompt are filled in when rendering.
+#[derive(Serialize, Default, Clone)]
+pub struct TemplateContext {
+    pub date: String,
+    pub weekday: String,
+    pub time: String,
+    pub city: String,
+    pub weather: String,
+    /// Open tasks carried over from an earlier entry, a `- [ ]` line each
+    pub carried_tasks: String,
+    /// The configured habits, a `- [ ]` line each
+    pub habits: String,
+    pub prompt: String,
+}
+
+fn default_morning_template() -> String {
+    format!(
+        "{} {{{{city}}}}\n{} {{{{weather}}}}\n# Tasks for today\n{{{{carried_tasks}}}}{{{{habits}}}}",
+        CITY_PREFIX, WEATHER_PREFIX
+    )
+}
+
+/// The morning entry for `date`, from the template for its weekday when there is one.
+pub fn render_morning(
+    templates: &TemplateConfig,
+    date: NaiveDate,
+    context: TemplateContext,
+) -> String {
+    let file = templates
+        .weekdays
+        .get(&date.weekday())
+        .unwrap_or(&templates.morning);
+    render(file, &default_morning_template(), date, context)
+}
+
+/// The section a check-in on `date` adds to the entry.
+pub fn render_check_in(templates: &TemplateConfig, date: NaiveDate) -> String {
+    render(
+        &templates.check_in,
+        CHECK_IN_TEMPLATE,
+        date,
+        TemplateContext::default(),
+    )
+}
+
+/// Renders the template `file`, falling back to `default` when it's missing or broken.
+fn render(file: &str, default: &str, date: NaiveDate, mut context: TemplateContext) -> String {
+    context.date = date.format("%Y-%m-%d").to_string();
+    context.weekday = date.format("%A").to_string();
+    context.time = Local::now().format("%H:%M").to_string();
+    context.prompt = writing_prompt(date);
+
+    let mut handlebars = Handlebars::new();
+    // Entries are Markdown, nothing to escape
+    handlebars.register_escape_fn(handlebars::no_escape);
+    let template = load(&ConfigManager::templates_dir().join(file), default);
+    match handlebars.render_template(&template, &context) {
+        Ok(rendered) => rendered,
+        Err(err) => {
+            eprintln!(
+                "Template {} is broken, using the built-in one: {}",
+                file, err
+            );
+            handlebars
+                .render_template(default, &context)
+                .expect("built-in templates render")
+        }
+    }
+}
+
+/// Reads a template, leaving a copy of `default` to edit when there's none yet.
+fn load(path: &Path, default: &str) -> String {
+    if let Ok(template) = fs::read_to_string(path) {
+        return template;
+    }
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(path, default));
+    if let Err(err) = written {
+        eprintln!("Couldn't write the template {}: {}", path.display(), err);
+    }
+    default.to_string()
+}
+
+/// Today's writing prompt, from `prompts.txt` or the built-in list.
+fn writing_prompt(date: NaiveDate) -> String {
+    let custom: Vec<String> = fs::read_to_string(ConfigManager::templates_dir().join(PROMPTS_FILE))
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    let day = date.num_days_from_ce() as usize;
+    if custom.is_empty() {
+        WRITING_PROMPTS[day % WRITING_PROMPTS.len()].to_string()
+    } else {
+        custom[day % custom.len()].clone()
+    }
+}